@@ -0,0 +1,294 @@
+//! Write [statisk] plugins in Rust.
+//!
+//! A plugin is a `cdylib` built for `wasm32-unknown-unknown` that adds
+//! template filters and functions, or transforms the content of pages before
+//! and after it is converted to HTML:
+//!
+//! ```no_run
+//! use statisk_plugin::{Page, Plugin, Value, export_plugin};
+//!
+//! fn shout(args: &[Value]) -> Result<Value, String> {
+//!     match args.first() {
+//!         Some(Value::String(s)) => Ok(Value::from(s.to_uppercase())),
+//!         _ => Err("shout expects a string".to_string()),
+//!     }
+//! }
+//!
+//! fn wave(_page: &Page, content: &str) -> Result<String, String> {
+//!     Ok(content.replace(":wave:", "👋"))
+//! }
+//!
+//! export_plugin!(Plugin::new().filter("shout", shout).before_html(wave));
+//! ```
+//!
+//! Plugins run sandboxed, without access to the file system, network or
+//! clock, and with limits on how long they run and how much memory they use.
+//!
+//! [statisk]: https://github.com/sondr3/statisk
+
+use serde::{Deserialize, Serialize};
+pub use serde_json::Value;
+
+/// A template filter or function, called with its arguments. Filters get the
+/// value they are applied to as the first argument.
+pub type Callback = fn(&[Value]) -> Result<Value, String>;
+
+/// Transforms the content of a page.
+pub type Transform = fn(&Page, &str) -> Result<String, String>;
+
+/// The page a transform is run for.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Page {
+    /// The path of the source file, relative to `content/`.
+    pub path: String,
+    pub frontmatter: Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "call", rename_all = "snake_case")]
+enum Request {
+    Manifest,
+    Filter { name: String, args: Vec<Value> },
+    Function { name: String, args: Vec<Value> },
+    BeforeHtml { page: Page, content: String },
+    AfterHtml { page: Page, content: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Ok(Value),
+    Error(String),
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    filters: Vec<&'a str>,
+    functions: Vec<&'a str>,
+    before_html: bool,
+    after_html: bool,
+}
+
+/// Everything a plugin adds, exported with [`export_plugin!`].
+#[derive(Debug, Default, Clone)]
+pub struct Plugin {
+    filters: Vec<(&'static str, Callback)>,
+    functions: Vec<(&'static str, Callback)>,
+    before_html: Option<Transform>,
+    after_html: Option<Transform>,
+}
+
+impl Plugin {
+    pub fn new() -> Self {
+        Plugin::default()
+    }
+
+    /// Adds the filter `name`, used like `{{ value|name(args) }}`.
+    pub fn filter(mut self, name: &'static str, filter: Callback) -> Self {
+        self.filters.push((name, filter));
+        self
+    }
+
+    /// Adds the function `name`, used like `{{ name(args) }}`.
+    pub fn function(mut self, name: &'static str, function: Callback) -> Self {
+        self.functions.push((name, function));
+        self
+    }
+
+    /// Transforms the source of pages before it is converted to HTML.
+    pub fn before_html(mut self, transform: Transform) -> Self {
+        self.before_html = Some(transform);
+        self
+    }
+
+    /// Transforms the HTML of pages after they are converted.
+    pub fn after_html(mut self, transform: Transform) -> Self {
+        self.after_html = Some(transform);
+        self
+    }
+
+    /// Answers a JSON request from statisk with a JSON response.
+    pub fn handle(&self, request: &[u8]) -> Vec<u8> {
+        let response = match serde_json::from_slice(request) {
+            Ok(request) => match self.dispatch(request) {
+                Ok(value) => Response::Ok(value),
+                Err(err) => Response::Error(err),
+            },
+            Err(err) => Response::Error(format!("Invalid request: {err}")),
+        };
+
+        serde_json::to_vec(&response).unwrap_or_default()
+    }
+
+    fn dispatch(&self, request: Request) -> Result<Value, String> {
+        match request {
+            Request::Manifest => {
+                let manifest = Manifest {
+                    filters: self.filters.iter().map(|(name, _)| *name).collect(),
+                    functions: self.functions.iter().map(|(name, _)| *name).collect(),
+                    before_html: self.before_html.is_some(),
+                    after_html: self.after_html.is_some(),
+                };
+                serde_json::to_value(manifest).map_err(|err| err.to_string())
+            }
+            Request::Filter { name, args } => find(&self.filters, &name, "filter")?(&args),
+            Request::Function { name, args } => find(&self.functions, &name, "function")?(&args),
+            Request::BeforeHtml { page, content } => match self.before_html {
+                Some(transform) => transform(&page, &content).map(Value::from),
+                None => Ok(Value::from(content)),
+            },
+            Request::AfterHtml { page, content } => match self.after_html {
+                Some(transform) => transform(&page, &content).map(Value::from),
+                None => Ok(Value::from(content)),
+            },
+        }
+    }
+}
+
+fn find(callbacks: &[(&str, Callback)], name: &str, kind: &str) -> Result<Callback, String> {
+    callbacks
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, callback)| *callback)
+        .ok_or_else(|| format!("Unknown {kind} {name}"))
+}
+
+/// The functions [`export_plugin!`] exports, which statisk calls with
+/// pointers into the memory of the plugin.
+#[doc(hidden)]
+pub mod __private {
+    use crate::Plugin;
+
+    /// Allocates `len` bytes for statisk to write a request into.
+    pub fn alloc(len: u32) -> u32 {
+        let buf = vec![0u8; len as usize].into_boxed_slice();
+        Box::into_raw(buf) as *mut u8 as usize as u32
+    }
+
+    /// Frees a response once statisk has read it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must come from [`alloc`] or [`call`].
+    pub unsafe fn free(ptr: u32, len: u32) {
+        let buf = std::ptr::slice_from_raw_parts_mut(ptr as usize as *mut u8, len as usize);
+        drop(unsafe { Box::from_raw(buf) });
+    }
+
+    /// Handles the request at `ptr`, which is freed, and returns a pointer to
+    /// the response in the upper 32 bits and its length in the lower.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must come from [`alloc`].
+    pub unsafe fn call(plugin: &Plugin, ptr: u32, len: u32) -> u64 {
+        let buf = std::ptr::slice_from_raw_parts_mut(ptr as usize as *mut u8, len as usize);
+        let request = unsafe { Box::from_raw(buf) };
+
+        let response = plugin.handle(&request).into_boxed_slice();
+        let len = response.len() as u64;
+        let ptr = Box::into_raw(response) as *mut u8 as usize as u64;
+        (ptr << 32) | len
+    }
+}
+
+/// Exports a [`Plugin`] for statisk to load, created the first time it is
+/// called.
+#[macro_export]
+macro_rules! export_plugin {
+    ($plugin:expr) => {
+        static STATISK_PLUGIN: ::std::sync::OnceLock<$crate::Plugin> = ::std::sync::OnceLock::new();
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn statisk_alloc(len: u32) -> u32 {
+            $crate::__private::alloc(len)
+        }
+
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn statisk_free(ptr: u32, len: u32) {
+            unsafe { $crate::__private::free(ptr, len) }
+        }
+
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn statisk_call(ptr: u32, len: u32) -> u64 {
+            let plugin = STATISK_PLUGIN.get_or_init(|| $plugin);
+            unsafe { $crate::__private::call(plugin, ptr, len) }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::{Page, Plugin};
+
+    fn plugin() -> Plugin {
+        Plugin::new()
+            .filter("double", |args| match args {
+                [Value::Number(n), ..] => Ok(json!(n.as_i64().unwrap_or_default() * 2)),
+                _ => Err("double expects a number".to_string()),
+            })
+            .function("answer", |_| Ok(json!(42)))
+            .after_html(|page: &Page, content| Ok(format!("{content}<!-- {} -->", page.path)))
+    }
+
+    fn handle(plugin: &Plugin, request: Value) -> Value {
+        serde_json::from_slice(&plugin.handle(request.to_string().as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_manifest() {
+        assert_eq!(
+            handle(&plugin(), json!({ "call": "manifest" })),
+            json!({ "ok": {
+                "filters": ["double"],
+                "functions": ["answer"],
+                "before_html": false,
+                "after_html": true,
+            }})
+        );
+    }
+
+    #[test]
+    fn test_handle() {
+        let plugin = plugin();
+        assert_eq!(
+            handle(
+                &plugin,
+                json!({ "call": "filter", "name": "double", "args": [21] })
+            ),
+            json!({ "ok": 42 })
+        );
+        assert_eq!(
+            handle(
+                &plugin,
+                json!({ "call": "filter", "name": "double", "args": ["a"] })
+            ),
+            json!({ "error": "double expects a number" })
+        );
+        assert_eq!(
+            handle(
+                &plugin,
+                json!({ "call": "function", "name": "missing", "args": [] })
+            ),
+            json!({ "error": "Unknown function missing" })
+        );
+
+        let page = json!({ "path": "index.kladd", "frontmatter": null });
+        assert_eq!(
+            handle(
+                &plugin,
+                json!({ "call": "before_html", "page": page, "content": "hi" })
+            ),
+            json!({ "ok": "hi" })
+        );
+        assert_eq!(
+            handle(
+                &plugin,
+                json!({ "call": "after_html", "page": page, "content": "<p>hi</p>" })
+            ),
+            json!({ "ok": "<p>hi</p><!-- index.kladd -->" })
+        );
+    }
+}
@@ -27,6 +27,7 @@ pub struct PublicFile {
 
 #[derive(Debug, Serialize, Clone)]
 pub struct Asset {
+    pub source: PathBuf,
     pub source_name: String,
     pub build_path: PathBuf,
     pub content: String,
@@ -38,6 +39,7 @@ impl Asset {
         let filename = filename(path);
 
         Ok(Self {
+            source: path.to_owned(),
             source_name: filename.clone(),
             build_path: path.to_owned(),
             content,
@@ -56,11 +58,13 @@ impl Asset {
 
         Ok(match mode {
             BuildMode::Optimized => Self {
+                source: path.to_owned(),
                 source_name,
                 build_path: digest_filename(path, &css.code),
                 content: minify::css(&css.code.clone())?,
             },
             BuildMode::Normal => Self {
+                source: path.to_owned(),
                 source_name,
                 build_path: path.to_owned(),
                 content: css.code,
@@ -68,6 +72,43 @@ impl Asset {
         })
     }
 
+    /// Builds one of the scripts bundled with statisk, written to the root of
+    /// `out`.
+    pub fn build_builtin_js(name: &str, source: &str, out: &Path, mode: BuildMode) -> Self {
+        let path = Path::new(name);
+
+        match mode {
+            BuildMode::Optimized => Self {
+                source: path.to_owned(),
+                source_name: name.to_string(),
+                build_path: out.join(digest_filename(path, source)),
+                content: minify::js(source, None),
+            },
+            BuildMode::Normal => Self {
+                source: path.to_owned(),
+                source_name: name.to_string(),
+                build_path: out.join(path),
+                content: source.to_string(),
+            },
+        }
+    }
+
+    /// Builds an asset generated by statisk, written to the root of `out`.
+    pub fn build_generated(name: &str, content: String, out: &Path, mode: BuildMode) -> Self {
+        let path = Path::new(name);
+        let build_path = match mode {
+            BuildMode::Optimized => out.join(digest_filename(path, &content)),
+            BuildMode::Normal => out.join(path),
+        };
+
+        Self {
+            source: path.to_owned(),
+            source_name: name.to_string(),
+            build_path,
+            content,
+        }
+    }
+
     pub fn build_js(path: &Path, mode: BuildMode) -> Result<Self> {
         let source = read_to_string(path)?;
         let source_name = path
@@ -79,11 +120,13 @@ impl Asset {
 
         Ok(match mode {
             BuildMode::Optimized => Self {
+                source: path.to_owned(),
                 source_name,
                 build_path: digest_filename(path, &source),
                 content: minify::js(&source, Some(ext)),
             },
             BuildMode::Normal => Self {
+                source: path.to_owned(),
                 source_name,
                 build_path: path.to_owned(),
                 content: source,
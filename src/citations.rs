@@ -0,0 +1,282 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use ahash::AHashMap;
+use anyhow::{Context, Result, anyhow, bail};
+use hayagriva::{
+    BibliographyDriver, BibliographyRequest, BufWriteFormat, CitationItem, CitationRequest,
+    ElemChildren, Library, Rendered,
+    archive::{ArchivedStyle, locales},
+    citationberg::{IndependentStyle, Style, json::Item},
+};
+use serde::{Deserialize, Serialize};
+
+/// The `[bibliography]` section of `statisk.toml`, the file is relative to
+/// the site root and the style is any CSL style bundled with hayagriva.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BibliographyConfig {
+    pub file: Option<PathBuf>,
+    pub style: String,
+}
+
+impl Default for BibliographyConfig {
+    fn default() -> Self {
+        BibliographyConfig {
+            file: None,
+            style: "ieee".to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Entries {
+    Library(Library),
+    Csl(AHashMap<String, Item>),
+}
+
+#[derive(Debug)]
+pub struct Bibliography {
+    entries: Entries,
+    style: IndependentStyle,
+}
+
+/// The rendered citations of a page, in the order they appear, and its
+/// bibliography.
+#[derive(Debug, Default)]
+pub struct Citations {
+    pub citations: Vec<String>,
+    pub bibliography: String,
+}
+
+impl Bibliography {
+    /// Loads a BibTeX/BibLaTeX (`.bib`), CSL-JSON (`.json`) or hayagriva
+    /// (`.yml`) file.
+    pub fn load(path: &Path, style: &str) -> Result<Self> {
+        let file = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read bibliography {}", path.display()))?;
+
+        let entries = match path.extension().and_then(|e| e.to_str()) {
+            Some("bib") => {
+                Entries::Library(hayagriva::io::from_biblatex_str(&file).map_err(|errors| {
+                    let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+                    anyhow!(
+                        "Invalid bibliography {}: {}",
+                        path.display(),
+                        errors.join(", ")
+                    )
+                })?)
+            }
+            Some("json") => Entries::Csl(
+                serde_json::from_str::<Vec<Item>>(&file)
+                    .with_context(|| format!("Invalid bibliography {}", path.display()))?
+                    .into_iter()
+                    .filter_map(|item| Some((item.id()?.into_owned(), item)))
+                    .collect(),
+            ),
+            Some("yml" | "yaml") => Entries::Library(
+                hayagriva::io::from_yaml_str(&file)
+                    .with_context(|| format!("Invalid bibliography {}", path.display()))?,
+            ),
+            _ => bail!(
+                "Unsupported bibliography {}, expected a .bib, .json or .yml file",
+                path.display()
+            ),
+        };
+
+        Ok(Bibliography {
+            entries,
+            style: style_by_name(style)?,
+        })
+    }
+
+    /// Renders each group of citation keys and a bibliography of everything
+    /// cited.
+    pub fn cite(&self, groups: &[Vec<String>]) -> Result<Citations> {
+        let locales = locales();
+
+        // The entry types differ but hayagriva does not export the trait they
+        // share, so the driver code is repeated for both
+        macro_rules! render {
+            ($get:expr) => {{
+                let mut driver = BibliographyDriver::new();
+                for keys in groups {
+                    let items = keys
+                        .iter()
+                        .map(|key| {
+                            $get(key.as_str())
+                                .map(CitationItem::with_entry)
+                                .ok_or_else(|| anyhow!("Unknown citation key @{key}"))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    driver.citation(CitationRequest::from_items(items, &self.style, &locales));
+                }
+
+                driver.finish(BibliographyRequest {
+                    style: &self.style,
+                    locale: None,
+                    locale_files: &locales,
+                })
+            }};
+        }
+
+        let rendered = match &self.entries {
+            Entries::Library(library) => render!(|key| library.get(key)),
+            Entries::Csl(items) => render!(|key| items.get(key)),
+        };
+
+        to_html(rendered)
+    }
+}
+
+/// Replaces `[@key]` and `[@key; @other]` citations in `source` with
+/// placeholders, returning the new source and the keys of each citation to
+/// [`substitute`] back in after converting the source to HTML.
+pub fn extract(source: &str) -> (String, Vec<Vec<String>>) {
+    let mut expanded = String::with_capacity(source.len());
+    let mut groups = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find("[@") {
+        let Some(len) = rest[start..].find(']') else {
+            break;
+        };
+
+        expanded.push_str(&rest[..start]);
+        match keys(&rest[start + 1..start + len]) {
+            Some(keys) => {
+                expanded.push_str(&placeholder(groups.len()));
+                groups.push(keys);
+            }
+            None => expanded.push_str(&rest[start..start + len + 1]),
+        }
+        rest = &rest[start + len + 1..];
+    }
+
+    expanded.push_str(rest);
+    (expanded, groups)
+}
+
+pub fn substitute(html: String, citations: &[String]) -> String {
+    citations
+        .iter()
+        .enumerate()
+        .fold(html, |html, (i, citation)| {
+            html.replace(&placeholder(i), citation)
+        })
+}
+
+fn placeholder(index: usize) -> String {
+    format!("STATISKCITE{index}END")
+}
+
+/// Parses `@key; @other` into keys, or nothing if it is not a citation.
+fn keys(citation: &str) -> Option<Vec<String>> {
+    citation
+        .split(';')
+        .map(|key| {
+            let key = key.trim().strip_prefix('@')?;
+            let valid = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '.' | '/'));
+            valid.then(|| key.to_string())
+        })
+        .collect()
+}
+
+fn style_by_name(name: &str) -> Result<IndependentStyle> {
+    let style =
+        ArchivedStyle::by_name(name).ok_or_else(|| anyhow!("Unknown citation style {name}"))?;
+    match style.get() {
+        Style::Independent(style) => Ok(style),
+        Style::Dependent(_) => bail!("Citation style {name} is not supported"),
+    }
+}
+
+fn to_html(rendered: Rendered) -> Result<Citations> {
+    let write = |children: &ElemChildren| -> Result<String> {
+        let mut html = String::new();
+        children.write_buf(&mut html, BufWriteFormat::Html)?;
+        Ok(html)
+    };
+
+    let citations = rendered
+        .citations
+        .iter()
+        .map(|c| Ok(format!("<cite>{}</cite>", write(&c.citation)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut bibliography = String::new();
+    if let Some(rendered) = rendered.bibliography {
+        bibliography.push_str("<div class=\"bibliography\">");
+        for item in rendered.items {
+            write!(
+                bibliography,
+                "<div class=\"csl-entry\" id=\"ref-{}\">",
+                item.key
+            )?;
+            if let Some(first) = item.first_field {
+                bibliography.push_str("<span class=\"csl-left-margin\">");
+                first.write_buf(&mut bibliography, BufWriteFormat::Html)?;
+                bibliography.push_str("</span>");
+            }
+            write!(bibliography, "{}</div>", write(&item.content)?)?;
+        }
+        bibliography.push_str("</div>");
+    }
+
+    Ok(Citations {
+        citations,
+        bibliography,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Bibliography, extract, substitute};
+    use crate::utils::TestDir;
+
+    const BIB: &str = r#"@article{knuth84,
+  author = {Donald E. Knuth},
+  title = {Literate Programming},
+  journal = {The Computer Journal},
+  year = {1984},
+}"#;
+
+    #[test]
+    fn test_extract() {
+        let (source, groups) = extract("See [@knuth84; @dijkstra68], [link](x) or [@ not this]");
+        assert_eq!(source, "See STATISKCITE0END, [link](x) or [@ not this]");
+        assert_eq!(groups, vec![vec!["knuth84", "dijkstra68"]]);
+    }
+
+    #[test]
+    fn test_cite() {
+        let dir = TestDir::new("citations");
+        let path = dir.path().join("refs.bib");
+        std::fs::write(&path, BIB).unwrap();
+
+        let bibliography = Bibliography::load(Path::new(&path), "ieee").unwrap();
+        let citations = bibliography.cite(&[vec!["knuth84".to_string()]]).unwrap();
+
+        assert_eq!(
+            substitute(
+                "<p>As STATISKCITE0END.</p>".to_string(),
+                &citations.citations
+            ),
+            "<p>As <cite>[1]</cite>.</p>"
+        );
+        assert!(citations.bibliography.contains("id=\"ref-knuth84\""));
+        assert!(citations.bibliography.contains("Literate Programming"));
+
+        let err = bibliography
+            .cite(&[vec!["missing".to_string()]])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown citation key @missing");
+    }
+}
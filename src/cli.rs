@@ -9,6 +9,9 @@ use clap_complete::{Generator, Shell, generate};
 pub struct Options {
     #[arg(long, short)]
     pub verbose: bool,
+    /// Number of threads to build with, defaults to the number of CPUs
+    #[arg(long, short, value_name = "N", global = true)]
+    pub jobs: Option<usize>,
     /// Directory to run in
     #[arg(value_hint = ValueHint::DirPath, value_name = "dir", global = true)]
     pub dir: Option<PathBuf>,
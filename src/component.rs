@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use minijinja::{Environment, ErrorKind, Value, context};
+
+use crate::markup::{kladd_to_html, to_html};
+
+const COMPONENTS_DIR: &str = "_components";
+
+#[derive(Debug, PartialEq)]
+struct Directive<'a> {
+    start: usize,
+    end: usize,
+    name: &'a str,
+    attributes: BTreeMap<String, String>,
+    inner: &'a str,
+}
+
+/// Renders kladd `source` to HTML, replacing every directive that has a
+/// matching `templates/_components/<name>.html` with the rendered template.
+/// Returns `None` when the source uses no components.
+pub fn render(source: &str, env: &Environment) -> Result<Option<String>> {
+    let mut expanded = String::with_capacity(source.len());
+    let mut components = Vec::new();
+    let mut last = 0;
+    let mut i = 0;
+
+    while let Some(offset) = source[i..].find('!') {
+        let start = i + offset;
+        i = start + 1;
+
+        if start > 0 && source.as_bytes()[start - 1] == b'\\' {
+            continue;
+        }
+        let Some(directive) = directive(source, start) else {
+            continue;
+        };
+        let Some(html) = render_component(&directive, env)? else {
+            continue;
+        };
+
+        expanded.push_str(&source[last..start]);
+        expanded.push_str(&placeholder(components.len()));
+        components.push(html);
+        last = directive.end;
+        i = directive.end;
+    }
+
+    if components.is_empty() {
+        return Ok(None);
+    }
+
+    expanded.push_str(&source[last..]);
+    let mut html = kladd_to_html(&expanded)?;
+    for (i, component) in components.iter().enumerate() {
+        let placeholder = placeholder(i);
+        html = html
+            .replace(&format!("<p>{placeholder}</p>"), component)
+            .replace(&placeholder, component);
+    }
+
+    Ok(Some(html))
+}
+
+fn render_component(directive: &Directive, env: &Environment) -> Result<Option<String>> {
+    let name = format!("{COMPONENTS_DIR}/{}.html", directive.name);
+    let template = match env.get_template(&name) {
+        Ok(template) => template,
+        Err(err) if err.kind() == ErrorKind::TemplateNotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Could not load {name}")),
+    };
+
+    let inner = to_html(directive.inner, env)?;
+    let inner = unwrap_paragraph(&inner);
+
+    let context = context! {
+        ..Value::from_serialize(&directive.attributes),
+        ..context! {
+            attributes => &directive.attributes,
+            content => Value::from_safe_string(inner),
+        }
+    };
+
+    template
+        .render(context)
+        .map(Some)
+        .with_context(|| format!("Could not render component {name}"))
+}
+
+/// Inline components like `!badge[new]` get their text without the paragraph
+/// kladd wraps it in.
+fn unwrap_paragraph(html: &str) -> String {
+    let trimmed = html.trim();
+    match trimmed
+        .strip_prefix("<p>")
+        .and_then(|p| p.strip_suffix("</p>"))
+    {
+        Some(inner) if !inner.contains("<p>") => inner.trim().to_string(),
+        _ => html.to_string(),
+    }
+}
+
+fn placeholder(index: usize) -> String {
+    format!("STATISKCOMPONENT{index}END")
+}
+
+/// Parses a `!name{key="value"}[inner]` directive starting at `start`, the
+/// attributes are optional.
+fn directive(source: &str, start: usize) -> Option<Directive<'_>> {
+    let bytes = source.as_bytes();
+    let name_end = (start + 1..bytes.len())
+        .find(|&i| !(bytes[i].is_ascii_alphanumeric() || bytes[i] == b'-' || bytes[i] == b'_'))?;
+    if name_end == start + 1 {
+        return None;
+    }
+
+    let mut pos = name_end;
+    let mut attributes = BTreeMap::new();
+    if bytes[pos] == b'{' {
+        let end = closing(bytes, pos, b'{', b'}')?;
+        attributes = parse_attributes(&source[pos + 1..end])?;
+        pos = end + 1;
+    }
+
+    if bytes.get(pos) != Some(&b'[') {
+        return None;
+    }
+
+    let end = closing(bytes, pos, b'[', b']')?;
+    Some(Directive {
+        start,
+        end: end + 1,
+        name: &source[start + 1..name_end],
+        attributes,
+        inner: &source[pos + 1..end],
+    })
+}
+
+fn closing(bytes: &[u8], open: usize, left: u8, right: u8) -> Option<usize> {
+    let mut depth = 0;
+    let mut quoted = false;
+    for (i, &b) in bytes.iter().enumerate().skip(open) {
+        match b {
+            b'"' if left == b'{' => quoted = !quoted,
+            _ if quoted => {}
+            b'\\' => {}
+            _ if b == left && (i == 0 || bytes[i - 1] != b'\\') => depth += 1,
+            _ if b == right && (i == 0 || bytes[i - 1] != b'\\') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn parse_attributes(input: &str) -> Option<BTreeMap<String, String>> {
+    let mut attributes = BTreeMap::new();
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let key = key.trim();
+        let value = value.trim_start();
+
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => value.split_at(value.find(char::is_whitespace).unwrap_or(value.len())),
+        };
+
+        attributes.insert(key.to_string(), value.to_string());
+        rest = remaining.trim_start();
+    }
+
+    Some(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{Directive, directive};
+
+    #[test]
+    fn test_directive() {
+        let source = r#"Hi !callout{kind="warning" id=x}[Careful, !em[really]] there"#;
+
+        assert_eq!(
+            directive(source, 3),
+            Some(Directive {
+                start: 3,
+                end: source.len() - 6,
+                name: "callout",
+                attributes: BTreeMap::from([
+                    ("id".to_string(), "x".to_string()),
+                    ("kind".to_string(), "warning".to_string()),
+                ]),
+                inner: "Careful, !em[really]",
+            })
+        );
+    }
+
+    #[test]
+    fn test_directive_without_attributes() {
+        let found = directive("!badge[new]", 0).unwrap();
+        assert_eq!(found.name, "badge");
+        assert!(found.attributes.is_empty());
+        assert_eq!(found.inner, "new");
+    }
+
+    #[test]
+    fn test_not_a_directive() {
+        assert_eq!(directive("Hello! there", 5), None);
+        assert_eq!(directive("!unclosed[text", 0), None);
+        assert_eq!(directive("!name{unclosed[text]", 0), None);
+    }
+}
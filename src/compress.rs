@@ -3,6 +3,7 @@ use std::{io::prelude::*, path::Path};
 use anyhow::{Context, Result};
 use brotli::{CompressorWriter, enc::BrotliEncoderParams};
 use flate2::{Compression, write::GzEncoder};
+use rayon::prelude::*;
 use walkdir::DirEntry;
 
 use crate::utils::{append_extension, find_files};
@@ -13,15 +14,16 @@ const VALID_EXTENSIONS: [&str; 15] = [
 ];
 
 pub fn folder(folder: &Path) -> Result<()> {
-    find_files(folder, compressible_files).try_for_each(|f| {
-        let content = std::fs::read(&f)?;
-        let gzip = append_extension(&f, "gz");
+    let files = find_files(folder, compressible_files).collect::<Vec<_>>();
+    files.par_iter().try_for_each(|f| {
+        let content = std::fs::read(f)?;
+        let gzip = append_extension(f, "gz");
 
         let mut gzip_encoder = GzEncoder::new(Vec::new(), Compression::best());
         gzip_encoder.write_all(&content)?;
         let gzipped = gzip_encoder.finish()?;
 
-        let brotli = append_extension(&f, "br");
+        let brotli = append_extension(f, "br");
         let brotli_params = BrotliEncoderParams::default();
         let mut brotli_encoder = CompressorWriter::with_params(Vec::new(), 4096, &brotli_params);
         brotli_encoder.write_all(&content)?;
@@ -1,18 +1,29 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::{Context, Result, anyhow, bail};
-use kladd::{ast::Document, html::to_html, parser::Parser};
-use minijinja::{context, value::Value};
+use minijinja::{Environment, context, value::Value};
 use serde::Serialize;
+use url::Url;
 
 use crate::{
     BuildMode,
+    citations::Bibliography,
     context::Context as SContext,
-    frontmatter::Frontmatter,
-    templating::{TemplatePath, create_base_context},
-    utils::{split_frontmatter, unprefixed_parent},
+    frontmatter::{Frontmatter, TemplateStage},
+    markup::{self, Markup},
+    og_image::OG_IMAGE,
+    plugins::Page,
+    search::SearchPage,
+    statisk_config::StatiskConfig,
+    templating::{blank_frontmatter, create_base_context, create_site_context},
+    utils::{extension, split_frontmatter, unprefixed_parent},
 };
 
+const HTML_FORMAT: &str = "html";
+
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Copy, Clone, Serialize)]
 pub enum ContentType {
@@ -36,17 +47,24 @@ impl ContentType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum ContentKind {
-    Kladd(Document),
+    Kladd(String),
     Other(String),
 }
 
 impl ContentKind {
-    fn get_content(&self) -> Result<String> {
+    pub fn get_content(
+        &self,
+        env: &Environment,
+        bibliography: Option<&Bibliography>,
+    ) -> Result<Markup> {
         match self {
-            ContentKind::Kladd(document) => Ok(to_html(document)?),
-            ContentKind::Other(str) => Ok(str.clone()),
+            ContentKind::Kladd(source) => markup::render(source, env, bibliography),
+            ContentKind::Other(str) => Ok(Markup {
+                html: str.clone(),
+                bibliography: None,
+            }),
         }
     }
 }
@@ -54,6 +72,7 @@ impl ContentKind {
 #[derive(Debug)]
 pub struct Content {
     pub source: PathBuf,
+    pub relative_source: String,
     pub out_path: PathBuf,
     pub dir: Option<String>,
     pub url: String,
@@ -68,36 +87,34 @@ impl Content {
         let stem = path.file_stem().unwrap().to_string_lossy();
         let stem = stem.as_ref();
 
-        let (frontmatter, content): (Frontmatter, ContentKind) = match kind {
-            ContentType::Kladd => {
-                let doc = Parser::new(&file)?;
-                let (doc, metadata): (_, Option<Frontmatter>) = doc.finish();
-                (metadata.unwrap(), ContentKind::Kladd(doc))
-            }
-            _ => {
-                let (frontmatter, content) = split_frontmatter(&file)
-                    .ok_or(anyhow!("Could not find content or frontmatter"))?;
-                let frontmatter = match (kind, frontmatter) {
-                    (ContentType::XML, None) => Frontmatter::empty(),
-                    (_, Some(fm)) => Frontmatter::deserialize(&fm)?,
-                    _ => bail!("Missing frontmatter in content"),
-                };
-                (frontmatter, ContentKind::Other(content))
-            }
+        let (frontmatter, content) =
+            split_frontmatter(&file).ok_or(anyhow!("Could not find content or frontmatter"))?;
+        let frontmatter = match (kind, frontmatter) {
+            (ContentType::XML, None) => Frontmatter::empty(),
+            (_, Some(fm)) => Frontmatter::deserialize(&fm)?,
+            _ => bail!("Missing frontmatter in content"),
+        };
+        // Kladd is only parsed after math, citations and components are taken
+        // out, with the frontmatter blanked out so positions match the file
+        let content = match kind {
+            ContentType::Kladd => ContentKind::Kladd(blank_frontmatter(&file)),
+            _ => ContentKind::Other(content),
         };
 
         let dir = unprefixed_parent(path, root);
-        let out_path = out_path(kind, path, dir.as_ref(), stem, &frontmatter);
+        let out_path = out_path(kind, path, dir.as_ref(), stem, &frontmatter)?;
 
-        let url = frontmatter.url(
-            &out_path
-                .parent()
-                .map(|p| p.to_string_lossy().to_string())
-                .unwrap_or_default(),
-        );
+        let url = url(&out_path);
+
+        let relative_source = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/");
 
         Ok(Content {
             source: path.to_path_buf(),
+            relative_source,
             url,
             kind,
             out_path,
@@ -107,84 +124,316 @@ impl Content {
         })
     }
 
-    pub fn render(&self, mode: BuildMode, context: &SContext) -> Result<String> {
+    pub fn render(&self, env: &Environment, mode: BuildMode, context: &SContext) -> Result<String> {
         match self.kind {
-            ContentType::HTML | ContentType::XML => self.render_template(mode, context),
-            ContentType::Kladd => self.render_content(mode, context),
+            ContentType::HTML | ContentType::XML => self.render_template(env, mode, context),
+            ContentType::Kladd => self.render_content(env, HTML_FORMAT, mode, context),
             ContentType::Unknown => bail!("Cannot render unknown files"),
         }
     }
 
-    pub fn filename(&self) -> String {
-        self.source.file_name().map_or_else(
-            || panic!("No filename found"),
-            |name| name.to_string_lossy().to_string(),
-        )
+    /// Renders one of the paths from [`Content::outputs`], alternate formats
+    /// are rendered through the matching `[page].<format>` layout.
+    pub fn render_output(
+        &self,
+        env: &Environment,
+        out_path: &Path,
+        mode: BuildMode,
+        context: &SContext,
+    ) -> Result<String> {
+        if out_path == self.out_path {
+            return self.render(env, mode, context);
+        }
+
+        self.render_content(env, &extension(out_path), mode, context)
+    }
+
+    /// All the files this content is written to, which is only `out_path`
+    /// unless public pages ask for more formats in their frontmatter or
+    /// section config.
+    pub fn outputs(&self, config: &StatiskConfig) -> Vec<PathBuf> {
+        if !self.is_public_page() {
+            return vec![self.out_path.clone()];
+        }
+
+        let formats = self
+            .frontmatter
+            .outputs
+            .as_ref()
+            .or_else(|| config.section_outputs(self.dir.as_deref()));
+
+        match formats {
+            Some(formats) => formats
+                .iter()
+                .map(|format| self.out_path.with_extension(format))
+                .collect(),
+            None => vec![self.out_path.clone()],
+        }
+    }
+
+    pub fn key(&self) -> String {
+        self.source.display().to_string()
     }
 
     pub fn is_public_page(&self) -> bool {
         matches!(self.kind, ContentType::Kladd | ContentType::HTML) && !self.is_special_page()
     }
 
-    pub fn context(&self, context: &SContext) -> Result<Value> {
-        let content = self.content.get_content()?;
+    pub fn is_searchable(&self) -> bool {
+        self.is_public_page() && self.frontmatter.search != Some(false)
+    }
+
+    pub fn search_page(&self) -> SearchPage {
+        SearchPage {
+            url: format!("/{}", self.url),
+            title: self.frontmatter.title.clone(),
+            description: self.frontmatter.description.clone(),
+        }
+    }
+
+    /// Renders the body of the content to HTML, running it through the
+    /// template engine before or after conversion when asked to in the
+    /// frontmatter.
+    pub fn body(&self, env: &Environment, context: &SContext) -> Result<Markup> {
+        let plugins = &context.templates.plugins;
+        let page = Page {
+            path: &self.relative_source,
+            frontmatter: self.frontmatter.to_context(),
+        };
+        let body = self.bibliography(context).and_then(|bibliography| {
+            let bibliography = bibliography.as_deref();
+            let ContentKind::Kladd(source) = &self.content else {
+                return self.content.get_content(env, bibliography);
+            };
+
+            let source = plugins.before_html(&page, source)?;
+            let mut markup = match self.frontmatter.template {
+                TemplateStage::Before => {
+                    let source = self.render_body(env, &source, context)?;
+                    markup::render(&source, env, bibliography)?
+                }
+                TemplateStage::After => {
+                    let mut markup = markup::render(&source, env, bibliography)?;
+                    markup.html = self.render_body(env, &markup.html, context)?;
+                    markup
+                }
+                TemplateStage::Never => markup::render(&source, env, bibliography)?,
+            };
+            markup.html = plugins.after_html(&page, &markup.html)?;
+            Ok(markup)
+        });
+
+        body.and_then(|mut body| {
+            body.html = context.templates.links.resolve(&body.html)?;
+            Ok(body)
+        })
+        .with_context(|| format!("Could not render {}", self.source.display()))
+    }
+
+    pub fn context(&self, env: &Environment, context: &SContext) -> Result<Value> {
+        let body = self.body(env, context)?;
         let frontmatter_context = self.frontmatter.to_context();
 
         Ok(context! {
             ..frontmatter_context,
             ..context! {
-                content => content,
+                content => body.html,
+                bibliography => body.bibliography,
+                og_image => self.og_image_url(&context.config)?,
                 canonical_url => context.config.url.join(&self.url)?,
             }
         })
     }
 
-    fn is_special_page(&self) -> bool {
-        self.out_path.as_os_str() == "404.html" || self.out_path.as_os_str() == "500.html"
+    pub fn summary(&self, context: &SContext) -> Result<Value> {
+        let frontmatter_context = self.frontmatter.to_context();
+
+        Ok(context! {
+            ..frontmatter_context,
+            ..context! {
+                canonical_url => context.config.url.join(&self.url)?,
+            }
+        })
+    }
+
+    fn render_body(&self, env: &Environment, body: &str, context: &SContext) -> Result<String> {
+        let context = context! {
+            ..create_site_context(context.mode, context),
+            ..self.frontmatter.to_context()
+        };
+
+        Ok(env.render_named_str(&self.source.display().to_string(), body, context)?)
     }
 
-    fn layout(&self) -> TemplatePath {
-        match &self.frontmatter.layout {
-            Some(layout) => TemplatePath(None, layout.to_string()),
-            None => TemplatePath(self.dir.clone(), "page".to_string()),
+    /// Where the preview image of a public page is written, next to the page.
+    pub fn og_image_path(&self) -> PathBuf {
+        self.out_path.with_file_name(OG_IMAGE)
+    }
+
+    /// Renders the preview image template with the frontmatter of the page.
+    pub fn og_image_svg(
+        &self,
+        env: &Environment,
+        template: &str,
+        context: &SContext,
+    ) -> Result<String> {
+        let ctx = context! {
+            ..create_site_context(context.mode, context),
+            ..self.frontmatter.to_context()
+        };
+
+        env.get_template(template)?.render(ctx).with_context(|| {
+            format!(
+                "Could not render preview image for {}",
+                self.source.display()
+            )
+        })
+    }
+
+    fn og_image_url(&self, config: &StatiskConfig) -> Result<Option<Url>> {
+        if config.og_image.template.is_none() || !self.is_public_page() {
+            return Ok(None);
         }
+
+        let path = self.og_image_path();
+        Ok(Some(config.url.join(&path.to_string_lossy())?))
     }
 
-    fn render_content(&self, mode: BuildMode, app_context: &SContext) -> Result<String> {
-        let base_context = create_base_context(mode, app_context);
-        let context = self.context(app_context)?;
-        let context = context! { ..base_context, ..context };
-        app_context
+    /// The bibliography from the frontmatter, relative to the content file, or
+    /// from the site config, only loaded when the content cites something.
+    fn bibliography(&self, context: &SContext) -> Result<Option<Arc<Bibliography>>> {
+        let ContentKind::Kladd(source) = &self.content else {
+            return Ok(None);
+        };
+        if !source.contains("[@") {
+            return Ok(None);
+        }
+
+        let path = match (
+            &self.frontmatter.bibliography,
+            &context.config.bibliography.file,
+        ) {
+            (Some(file), _) => self.source.parent().unwrap_or(Path::new("")).join(file),
+            (None, Some(file)) => file.clone(),
+            (None, None) => return Ok(None),
+        };
+
+        context.bibliography(&path).map(Some)
+    }
+
+    fn related(&self, context: &SContext) -> Result<Vec<Value>> {
+        let Some(related) = context.related.get(&self.key()) else {
+            return Ok(Vec::new());
+        };
+
+        related
+            .iter()
+            .filter_map(|key| context.pages.get(key))
+            .map(|page| page.summary(context))
+            .collect()
+    }
+
+    fn render_context(
+        &self,
+        env: &Environment,
+        mode: BuildMode,
+        app_context: &SContext,
+    ) -> Result<Value> {
+        let base_context = create_base_context(env, mode, app_context)?;
+        let context = app_context.page_context(env, self)?;
+        let related = self.related(app_context)?;
+
+        Ok(context! { ..base_context, ..context, ..context! { related => related } })
+    }
+
+    fn is_special_page(&self) -> bool {
+        self.out_path.as_os_str() == "404.html" || self.out_path.as_os_str() == "500.html"
+    }
+
+    fn render_content(
+        &self,
+        env: &Environment,
+        format: &str,
+        mode: BuildMode,
+        app_context: &SContext,
+    ) -> Result<String> {
+        let context = self.render_context(env, mode, app_context)?;
+        let layout = app_context
             .templates
-            .render_template(&self.layout(), context)
+            .find_layout(
+                self.frontmatter.layout.as_deref(),
+                self.dir.as_deref(),
+                format,
+            )
+            .with_context(|| format!("No layout for {}", self.source.display()))?;
+        app_context.templates.render_template(env, layout, context)
     }
 
-    fn render_template(&self, mode: BuildMode, app_context: &SContext) -> Result<String> {
-        let base_context = create_base_context(mode, app_context);
-        let context = self.context(app_context)?;
-        let context = context! { ..base_context, ..context };
-        let env = app_context.templates.environment.acquire_env()?;
-        let content = self.content.get_content()?;
-        let template = env.template_from_str(&content)?;
-        template.render(context).context("Could not render")
+    fn render_template(
+        &self,
+        env: &Environment,
+        mode: BuildMode,
+        app_context: &SContext,
+    ) -> Result<String> {
+        let context = self.render_context(env, mode, app_context)?;
+        let name = app_context.templates.template_name(&self.source);
+        let rendered = env
+            .get_template(&name)?
+            .render(context)
+            .context("Could not render")?;
+
+        // Frontmatter is blanked out to keep line numbers, which leaves empty
+        // lines before an XML declaration that has to come first
+        Ok(rendered.trim_start().to_string())
     }
 }
 
+fn url(out_path: &Path) -> String {
+    if out_path.file_name().is_some_and(|f| f == "index.html") {
+        let dir = out_path.parent().unwrap_or(Path::new(""));
+        if dir.as_os_str().is_empty() {
+            return String::new();
+        }
+
+        return format!("{}/", dir.to_string_lossy());
+    }
+
+    out_path.to_string_lossy().to_string()
+}
+
 fn out_path(
     kind: ContentType,
     path: &Path,
     dir: Option<&String>,
     stem: &str,
     frontmatter: &Frontmatter,
-) -> PathBuf {
-    match kind {
+) -> Result<PathBuf> {
+    if let Some(path) = &frontmatter.path
+        && matches!(kind, ContentType::HTML | ContentType::Kladd)
+    {
+        // The path is relative to the site, so it cannot leave the output directory
+        let path = path.trim_matches('/');
+        if !Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            bail!("Invalid path {path:?}, it cannot contain `..` or be absolute");
+        }
+
+        return Ok([path, "index.html"]
+            .into_iter()
+            .filter(|p| !p.is_empty())
+            .collect());
+    }
+
+    Ok(match kind {
         ContentType::XML => PathBuf::from(path.file_name().unwrap_or_default()),
         ContentType::HTML | ContentType::Unknown => {
             // First check if this is a special page (like 404.html)
             if let Some(filename) = path.file_name().and_then(|f| f.to_str())
                 && (filename == "404.html" || filename == "500.html")
             {
-                return PathBuf::from(filename);
+                return Ok(PathBuf::from(filename));
             }
 
             // Then handle regular pages
@@ -195,9 +444,191 @@ fn out_path(
                 (Some(dir), None) => [dir, "index.html"].into_iter().collect(),
             }
         }
-        ContentType::Kladd => match &frontmatter.slug {
-            Some(slug) => [slug, "index.html"].into_iter().collect(),
-            None => [stem, "index.html"].into_iter().collect(),
-        },
+        // Nested content keeps its directories, the slug only replaces the
+        // last segment and an `index` file becomes the directory itself
+        ContentType::Kladd => {
+            let name = match (&frontmatter.slug, stem) {
+                (Some(slug), _) => slug.as_str(),
+                (None, "index") => "",
+                (None, stem) => stem,
+            };
+
+            [dir.map_or("", String::as_str), name, "index.html"]
+                .into_iter()
+                .filter(|p| !p.is_empty())
+                .collect()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use anyhow::Result;
+
+    use super::{Content, ContentType, out_path, url};
+    use crate::{
+        BuildMode,
+        context::Context,
+        events::EventSender,
+        frontmatter::Frontmatter,
+        hooks::{Hooks, HooksConfig},
+        links::Links,
+        paths::Paths,
+        plugins::Plugins,
+        render::Renderer,
+        statisk_config::StatiskConfig,
+        templating::Templates,
+        utils::TestDir,
+    };
+
+    const BIB: &str = r#"@article{knuth84,
+  author = {Donald E. Knuth},
+  title = {Literate Programming},
+  journal = {The Computer Journal},
+  year = {1984},
+}"#;
+
+    /// Reads `source` as a content file in a site with a `badge` component and
+    /// a bibliography, and renders its body.
+    fn render_body(name: &str, config: &str, source: &str) -> Result<String> {
+        let dir = TestDir::new(name);
+        let root = dir.path();
+        fs::create_dir_all(root.join("templates/_components"))?;
+        fs::create_dir_all(root.join("content"))?;
+        fs::write(
+            root.join("statisk.toml"),
+            format!("url = \"https://example.com\"\n{config}"),
+        )?;
+        fs::write(
+            root.join("templates/_components/badge.html"),
+            r#"<span class="badge">{{ content }}</span>"#,
+        )?;
+        fs::write(root.join("refs.bib"), BIB)?;
+        let path = root.join("content/page.kladd");
+        fs::write(&path, source)?;
+
+        let mode = BuildMode::Optimized;
+        let paths = Paths::new(root);
+        let config = StatiskConfig::from_path(&root.join("statisk.toml"), mode)?;
+        let plugins = Arc::new(Plugins::load(root, &config.plugins)?);
+        let templates = Templates::new(&paths, &config, Links::new(&config.url), plugins)?;
+        let hooks = Hooks::new(&paths, mode, HooksConfig::default());
+        let renderer = Renderer::new(&paths);
+        let context = Context::new(templates, config, renderer, hooks, mode, EventSender::new());
+
+        let page = Content::from_path(&path, &paths.content, ContentType::Kladd)?;
+        let env = context.templates.environment.acquire_env()?;
+        Ok(page.body(&env, &context)?.html)
+    }
+
+    fn kladd_out_path(file: &str, slug: Option<&str>, path: Option<&str>) -> Result<PathBuf> {
+        let file = Path::new(file);
+        let frontmatter = Frontmatter {
+            slug: slug.map(ToString::to_string),
+            path: path.map(ToString::to_string),
+            ..Frontmatter::empty()
+        };
+        let dir = file
+            .parent()
+            .filter(|p| p.components().count() > 0)
+            .map(|p| p.to_string_lossy().to_string());
+        let stem = file.file_stem().unwrap().to_string_lossy();
+
+        out_path(ContentType::Kladd, file, dir.as_ref(), &stem, &frontmatter)
+    }
+
+    #[test]
+    fn test_nested_kladd_out_path() {
+        let cases = [
+            ("trip.kladd", None, None, "trip/index.html"),
+            (
+                "blog/2024/trip.kladd",
+                None,
+                None,
+                "blog/2024/trip/index.html",
+            ),
+            (
+                "blog/2024/trip.kladd",
+                Some("paris"),
+                None,
+                "blog/2024/paris/index.html",
+            ),
+            ("blog/index.kladd", None, None, "blog/index.html"),
+            ("index.kladd", None, None, "index.html"),
+            (
+                "blog/2024/trip.kladd",
+                Some("paris"),
+                Some("/trips/paris/"),
+                "trips/paris/index.html",
+            ),
+            ("pages/home.kladd", None, Some("/"), "index.html"),
+        ];
+
+        for (file, slug, path, expected) in cases {
+            assert_eq!(
+                kladd_out_path(file, slug, path).unwrap(),
+                PathBuf::from(expected)
+            );
+        }
+
+        for path in ["../../x", "/blog/../../x", "./x"] {
+            assert!(kladd_out_path("trip.kladd", None, Some(path)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_url() {
+        assert_eq!(url(Path::new("index.html")), "");
+        assert_eq!(
+            url(Path::new("blog/2024/trip/index.html")),
+            "blog/2024/trip/"
+        );
+        assert_eq!(url(Path::new("sitemap.xml")), "sitemap.xml");
+    }
+
+    #[test]
+    fn test_component_in_content() {
+        let html = render_body(
+            "components",
+            "",
+            "+++\ntitle = \"Page\"\n+++\n\nSomething !badge[new] here.\n",
+        )
+        .unwrap();
+        assert!(html.contains(r#"Something <span class="badge">new</span> here."#));
+    }
+
+    #[test]
+    fn test_math_in_content() {
+        let html = render_body(
+            "math",
+            "",
+            "+++\ntitle = \"Page\"\n+++\n\nHalf is $`\\frac{1}{2}`.\n",
+        )
+        .unwrap();
+        assert!(html.contains("<mfrac><mn>1</mn><mn>2</mn></mfrac>"));
+
+        let err = render_body(
+            "math-error",
+            "",
+            "+++\ntitle = \"Page\"\n+++\n\nBroken $`\\frac{1`.\n",
+        )
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("Invalid math at line 5, column 8"));
+    }
+
+    #[test]
+    fn test_citation_in_content() {
+        let source = "+++\ntitle = \"Page\"\n+++\n\nAs shown [@knuth84].\n";
+        let html = render_body("cite", "[bibliography]\nfile = \"refs.bib\"", source).unwrap();
+        assert!(html.contains("As shown <cite>[1]</cite>."));
+
+        let err = render_body("cite-missing", "", source).unwrap_err();
+        assert!(format!("{err:#}").contains("Cannot cite @knuth84 without a bibliography"));
     }
 }
@@ -1,17 +1,32 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-use ahash::AHashMap;
-use anyhow::{Context as _, Result};
+use ahash::{AHashMap, AHashSet};
+use anyhow::{Context as _, Result, anyhow, bail};
 use dashmap::DashMap;
+use minijinja::{Environment, Value};
+use rayon::prelude::*;
+use sha1_smol::{Digest, Sha1};
+use walkdir::DirEntry;
 
 use crate::{
     BuildMode,
     asset::{Asset, PublicFile, is_buildable_css_file, is_js},
+    citations::Bibliography,
     content::{Content, ContentType},
+    dates::Dates,
     events::{Event, EventSender},
+    hooks::{Hooks, Stage},
+    images::IMAGE_DIR,
     paths::{LIVERELOAD_JS, Paths},
+    related,
     render::Renderer,
+    search::{INDEX_DIR, SEARCH_JS},
     statisk_config::StatiskConfig,
+    svg::{self, SPRITE},
     templating::{Templates, is_page, is_partial},
     utils::{find_files, is_file},
 };
@@ -21,8 +36,20 @@ pub struct Context {
     renderer: Renderer,
     pub assets: Arc<DashMap<String, Asset>>,
     pub pages: Arc<DashMap<String, Content>>,
+    pub related: Arc<DashMap<String, Vec<String>>>,
+    /// The context of every page with its rendered content, kept until the
+    /// page changes so it is only rendered once per build.
+    contexts: DashMap<String, Value>,
+    /// The `pages` shared by every page, built from `contexts`.
+    pages_context: Mutex<Option<Value>>,
+    /// Bibliographies by path, loaded once per build for every page citing them.
+    bibliographies: DashMap<PathBuf, Arc<Bibliography>>,
     pub public_files: Vec<PublicFile>,
     pub templates: Templates,
+    pub hooks: Hooks,
+    dates: Dates,
+    /// The files the last page rebuild in dev mode read, by their hash.
+    read_files: Mutex<AHashMap<PathBuf, Digest>>,
     pub mode: BuildMode,
     events: EventSender,
 }
@@ -32,50 +59,70 @@ impl Context {
         templates: Templates,
         config: StatiskConfig,
         renderer: Renderer,
+        hooks: Hooks,
         mode: BuildMode,
         events: EventSender,
     ) -> Self {
+        let dates = Dates::new(config.dates);
         Self {
             config,
             renderer,
             assets: Arc::new(DashMap::new()),
             pages: Arc::new(DashMap::new()),
+            related: Arc::new(DashMap::new()),
+            contexts: DashMap::new(),
+            pages_context: Mutex::new(None),
+            bibliographies: DashMap::new(),
             public_files: Vec::new(),
             templates,
+            hooks,
+            dates,
+            read_files: Mutex::new(AHashMap::new()),
             mode,
             events,
         }
     }
 
     pub fn build(&self) -> Result<()> {
-        self.renderer.render_context(self)
+        self.invalidate(None);
+        self.update_related()?;
+        self.renderer.render_context(self)?;
+        self.hooks.run(Stage::PostRender)
     }
 
     pub fn collect(&mut self, paths: &Paths) -> Result<()> {
-        let pages = collect_content(paths)?;
-        let mut pages: AHashMap<_, _> = pages.into_iter().map(|p| (p.filename(), p)).collect();
-        pages.extend(
-            collect_pages(paths)?
-                .into_iter()
-                .map(|p| (p.filename(), p))
-                .collect::<Vec<_>>(),
-        );
-
-        for (key, page) in pages {
-            self.pages.insert(key, page);
+        for mut page in collect_content(paths)?
+            .into_iter()
+            .chain(collect_pages(paths)?)
+        {
+            self.dates.fill(&page.source, &mut page.frontmatter);
+            self.templates.links.insert(&page)?;
+            self.pages.insert(page.key(), page);
         }
 
-        for asset in collect_css(paths, self.mode)? {
-            self.assets.insert(asset.source_name.clone(), asset);
-        }
-        for asset in collect_js(paths, self.mode)? {
-            self.assets.insert(asset.source_name.clone(), asset);
+        for asset in collect_css(paths, self.mode)?
+            .into_iter()
+            .chain(collect_js(paths, self.mode)?)
+        {
+            if let Some(other) = self.assets.insert(asset.source_name.clone(), asset) {
+                bail!(
+                    "Found multiple assets named {}, {} and {}",
+                    other.source_name,
+                    other.source.display(),
+                    self.assets
+                        .get(&other.source_name)
+                        .unwrap()
+                        .source
+                        .display()
+                );
+            }
         }
 
         if self.mode.normal() {
             self.assets.insert(
                 "livereload.js".to_string(),
                 Asset {
+                    source: PathBuf::from("livereload.js"),
                     source_name: "livereload.js".to_string(),
                     build_path: paths.out.join(Path::new("livereload.js")),
                     content: LIVERELOAD_JS.to_string(),
@@ -83,8 +130,93 @@ impl Context {
             );
         }
 
+        if self.config.search.enabled {
+            let search = Asset::build_builtin_js("search.js", SEARCH_JS, &paths.out, self.mode);
+            self.assets.insert(search.source_name.clone(), search);
+        }
+
+        if self.config.icons.sprite {
+            let sprite = svg::sprite(&paths.icons).context("Could not build icon sprite")?;
+            let sprite = Asset::build_generated(SPRITE, sprite, &paths.out, self.mode);
+            self.assets.insert(sprite.source_name.clone(), sprite);
+        }
+
         self.public_files.extend(collect_public_files(paths));
 
+        self.check_collisions()
+    }
+
+    /// Makes sure no two pages, preview images, public files or assets are
+    /// written to the same file in the output directory, and that none of them
+    /// are written to the directories for processed images and search.
+    pub fn check_collisions(&self) -> Result<()> {
+        let pages = self
+            .pages
+            .iter()
+            .flat_map(|p| {
+                p.outputs(&self.config)
+                    .into_iter()
+                    .map(|out_path| (out_path, p.source.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let og_images = self
+            .pages
+            .iter()
+            .filter(|p| self.config.og_image.template.is_some() && p.is_public_page())
+            .map(|p| (p.og_image_path(), p.source.clone()))
+            .collect::<Vec<_>>();
+        let public_files = self.public_files.iter().map(|f| {
+            let out_path = f.path.strip_prefix(&f.prefix).unwrap_or(&f.path);
+            (out_path.to_path_buf(), f.path.clone())
+        });
+        let assets = self
+            .assets
+            .iter()
+            .map(|a| {
+                (
+                    PathBuf::from(a.build_path.file_name().unwrap()),
+                    a.source.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut outputs = pages
+            .into_iter()
+            .chain(og_images)
+            .chain(public_files)
+            .chain(assets)
+            .collect::<Vec<_>>();
+        outputs.sort();
+
+        let mut reserved = vec![(Path::new(IMAGE_DIR), "processed images")];
+        if self.config.search.enabled {
+            reserved.push((Path::new(INDEX_DIR), "the search index"));
+        }
+
+        let mut seen: AHashMap<PathBuf, PathBuf> = AHashMap::new();
+        for (out_path, source) in outputs {
+            if let Some((dir, used_for)) =
+                reserved.iter().find(|(dir, _)| out_path.starts_with(dir))
+            {
+                bail!(
+                    "{} is written to {}, but {}/ is used for {used_for}",
+                    source.display(),
+                    out_path.display(),
+                    dir.display()
+                );
+            }
+            if let Some(other) = seen.get(&out_path) {
+                bail!(
+                    "Both {} and {} are written to {}",
+                    other.display(),
+                    source.display(),
+                    out_path.display()
+                );
+            }
+            seen.insert(out_path, source);
+        }
+
         Ok(())
     }
 
@@ -95,23 +227,236 @@ impl Context {
         Ok(())
     }
 
-    pub fn update_page(&self, key: String, page: Content) -> Result<()> {
-        self.pages.insert(key, page);
+    /// Replaces every page with `pages` read again in dev mode, and renders
+    /// all of them once. Pages keep their rendered context when neither they
+    /// nor any URL changed, unless `rerender` is set for changes to something
+    /// every page can use, like components or scripts.
+    pub fn update_pages(&self, pages: Vec<Content>, rerender: bool) -> Result<()> {
+        let keys = self
+            .pages
+            .iter()
+            .map(|p| p.key().clone())
+            .collect::<Vec<_>>();
+        let previous = keys
+            .iter()
+            .filter_map(|key| self.pages.remove(key))
+            .collect::<AHashMap<_, _>>();
+        for mut page in pages {
+            self.dates.fill(&page.source, &mut page.frontmatter);
+            self.pages.insert(page.key(), page);
+        }
+
+        let pages = self.pages.iter().collect::<Vec<_>>();
+        let checked = self.check_collisions().and_then(|()| {
+            self.templates
+                .links
+                .replace(pages.iter().map(|p| p.value()))
+        });
+        drop(pages);
+        if let Err(err) = checked {
+            self.pages.clear();
+            for (key, page) in previous {
+                self.pages.insert(key, page);
+            }
+            return Err(err);
+        }
+
+        // Other pages link to and list pages by their URL, so they are all
+        // rendered again when one is added, removed or moved
+        let moved = self.pages.len() != previous.len()
+            || self
+                .pages
+                .iter()
+                .any(|p| previous.get(p.key()).is_none_or(|old| old.url != p.url));
+        if rerender || moved {
+            self.invalidate(None);
+        } else {
+            for page in self.pages.iter() {
+                let old = &previous[page.key()];
+                if old.content != page.content || old.frontmatter != page.frontmatter {
+                    self.invalidate(Some(page.key()));
+                }
+            }
+        }
+
+        self.update_related()?;
         self.renderer.write_content(self)?;
         self.events.tx.send(Event::Reload).context("event failed")?;
         Ok(())
     }
+
+    /// The context of `page`, rendered once and reused until it changes.
+    pub fn page_context(&self, env: &Environment, page: &Content) -> Result<Value> {
+        let key = page.key();
+        if let Some(context) = self.contexts.get(&key) {
+            return Ok(context.clone());
+        }
+
+        let context = page.context(env, self)?;
+        self.contexts.insert(key, context.clone());
+        Ok(context)
+    }
+
+    /// Renders the context of every page not cached yet on the thread pool,
+    /// followed by `pages`.
+    pub fn prepare_contexts(&self, env: &Environment) -> Result<()> {
+        let pages = self.pages.iter().collect::<Vec<_>>();
+        pages.par_iter().try_for_each(|page| {
+            self.page_context(env, page.value())
+                .map(|_| ())
+                .map_err(|err| self.templates.diagnose(err, &page.source))
+        })?;
+        self.pages_context(env)?;
+        Ok(())
+    }
+
+    /// Every public page sorted by source, built once and shared by all pages
+    /// as `pages`.
+    pub fn pages_context(&self, env: &Environment) -> Result<Value> {
+        let mut pages_context = self
+            .pages_context
+            .lock()
+            .map_err(|_| anyhow!("Could not lock pages"))?;
+        if let Some(pages) = &*pages_context {
+            return Ok(pages.clone());
+        }
+
+        let mut pages = self
+            .pages
+            .iter()
+            .filter(|c| c.value().is_public_page())
+            .collect::<Vec<_>>();
+        pages.sort_by(|a, b| a.key().cmp(b.key()));
+        let pages = pages
+            .iter()
+            .map(|c| self.page_context(env, c.value()))
+            .collect::<Result<Vec<_>>>()?;
+        let pages = Value::from(pages);
+        *pages_context = Some(pages.clone());
+        Ok(pages)
+    }
+
+    /// The bibliography at `path`, loaded once and reused until the next build.
+    pub fn bibliography(&self, path: &Path) -> Result<Arc<Bibliography>> {
+        if let Some(bibliography) = self.bibliographies.get(path) {
+            return Ok(bibliography.clone());
+        }
+
+        let bibliography = Arc::new(Bibliography::load(path, &self.config.bibliography.style)?);
+        self.bibliographies
+            .insert(path.to_path_buf(), bibliography.clone());
+        Ok(bibliography)
+    }
+
+    /// Forgets the rendered context of the page at `key`, or of every page
+    /// and the bibliographies they cite, and with it `pages`.
+    fn invalidate(&self, key: Option<&str>) {
+        match key {
+            Some(key) => {
+                self.contexts.remove(key);
+            }
+            None => {
+                self.contexts.clear();
+                self.bibliographies.clear();
+            }
+        }
+        if let Ok(mut pages) = self.pages_context.lock() {
+            *pages = None;
+        }
+    }
+
+    /// Runs a rebuild in dev mode, logging and sending errors to the browser
+    /// instead of stopping live reload.
+    pub fn rebuild(&self, rebuild: impl FnOnce() -> Result<()>) {
+        if let Err(err) = rebuild() {
+            tracing::error!("{err:#}");
+            if self
+                .events
+                .tx
+                .send(Event::Error(format!("{err:#}")))
+                .is_err()
+            {
+                tracing::error!("Could not send error to the browser");
+            }
+        }
+    }
+
+    /// Rebuilds pages in dev mode with the build hooks around it, one rebuild
+    /// at a time. Asset rebuilds skip the hooks, as hooks often write CSS or JS
+    /// and would trigger themselves again. For the same reason `changed` files
+    /// that are the same as when the last rebuild read them, like those written
+    /// by `pre_build`, are skipped.
+    pub fn rebuild_pages(
+        &self,
+        paths: &Paths,
+        changed: &[PathBuf],
+        rebuild: impl FnOnce() -> Result<()>,
+    ) {
+        let mut read = match self.read_files.lock() {
+            Ok(read) => read,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if changed
+            .iter()
+            .all(|path| read.get(path) == file_digest(path).as_ref())
+        {
+            tracing::debug!("Skipping files the last rebuild read");
+            return;
+        }
+
+        self.rebuild(|| {
+            self.hooks.run(Stage::PreBuild)?;
+            // Before reading any page, so changes while rebuilding are not lost
+            *read = read_files(paths);
+            rebuild()?;
+            self.hooks.run(Stage::PostRender)?;
+            self.hooks.run(Stage::PostBuild)
+        });
+    }
+
+    /// Ranks related pages from their rendered contexts, which are prepared
+    /// in parallel first and reused when writing the pages.
+    fn update_related(&self) -> Result<()> {
+        let env = self.templates.environment.acquire_env()?;
+        self.prepare_contexts(&env)?;
+        let documents = related::collect(self, &env)?;
+        self.related.clear();
+        for (key, related) in related::rank(&documents, self.config.related.limit) {
+            self.related.insert(key, related);
+        }
+
+        Ok(())
+    }
 }
 
-fn collect_css(paths: &Paths, mode: BuildMode) -> Result<Vec<Asset>> {
-    find_files(&paths.css, is_buildable_css_file)
-        .map(|f| Asset::build_css(&f, mode))
+/// The hash of every template, script, data and content file of the site and
+/// its theme, by the path they are watched at.
+fn read_files(paths: &Paths) -> AHashMap<PathBuf, Digest> {
+    paths
+        .layers()
+        .flat_map(|p| [&p.templates, &p.scripts, &p.data])
+        .chain([&paths.content])
+        .filter_map(|dir| dir.canonicalize().ok())
+        .flat_map(|dir| find_files(&dir, is_file))
+        .filter_map(|path| file_digest(&path).map(|digest| (path, digest)))
         .collect()
 }
 
-fn collect_js(paths: &Paths, mode: BuildMode) -> Result<Vec<Asset>> {
-    find_files(&paths.js, is_js)
-        .map(|f| Asset::build_js(&f, mode))
+fn file_digest(path: &Path) -> Option<Digest> {
+    fs::read(path).ok().map(|bytes| Sha1::from(bytes).digest())
+}
+
+pub fn collect_css(paths: &Paths, mode: BuildMode) -> Result<Vec<Asset>> {
+    find_layered_files(paths, |p| &p.css, is_buildable_css_file)
+        .into_par_iter()
+        .map(|(_, f)| Asset::build_css(&f, mode))
+        .collect()
+}
+
+pub fn collect_js(paths: &Paths, mode: BuildMode) -> Result<Vec<Asset>> {
+    find_layered_files(paths, |p| &p.js, is_js)
+        .into_par_iter()
+        .map(|(_, f)| Asset::build_js(&f, mode))
         .collect()
 }
 
@@ -123,17 +468,47 @@ pub fn collect_content(paths: &Paths) -> Result<Vec<Content>> {
 }
 
 pub fn collect_pages(paths: &Paths) -> Result<Vec<Content>> {
-    find_files(&paths.templates, is_file)
-        .filter(|f| is_page(f))
-        .map(|f| Content::from_path(&f, &paths.templates, ContentType::from_ext(&f)?))
+    find_layered_files(paths, |p| &p.templates, is_file)
+        .into_iter()
+        .filter(|(dir, f)| is_page(f) && !in_partial_dir(dir, f))
+        .map(|(dir, f)| Content::from_path(&f, &dir, ContentType::from_ext(&f)?))
         .collect()
 }
 
+/// Every file in `dir` of the site and its theme as the directory it was
+/// found in and its path, files in the site replace those at the same path
+/// in the theme.
+pub fn find_layered_files<F>(
+    paths: &Paths,
+    dir: impl Fn(&Paths) -> &PathBuf,
+    filter: F,
+) -> Vec<(PathBuf, PathBuf)>
+where
+    F: Fn(&DirEntry) -> bool + Copy,
+{
+    let mut seen = AHashSet::new();
+    paths
+        .layers()
+        .flat_map(|layer| {
+            let dir = dir(layer);
+            find_files(dir, filter).map(|f| (dir.clone(), f))
+        })
+        .filter(|(dir, f)| seen.insert(f.strip_prefix(dir).unwrap_or(f).to_path_buf()))
+        .collect()
+}
+
+/// Directories starting with `_`, like `_components/`, only hold partials.
+fn in_partial_dir(root: &Path, path: &Path) -> bool {
+    path.strip_prefix(root)
+        .is_ok_and(|p| p.ancestors().skip(1).any(is_partial))
+}
+
 fn collect_public_files(paths: &Paths) -> Vec<PublicFile> {
-    find_files(&paths.public, is_file)
-        .map(|f| PublicFile {
+    find_layered_files(paths, |p| &p.public, is_file)
+        .into_iter()
+        .map(|(dir, f)| PublicFile {
             path: f,
-            prefix: paths.public.display().to_string(),
+            prefix: dir.display().to_string(),
         })
         .collect()
 }
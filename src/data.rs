@@ -0,0 +1,44 @@
+use std::{collections::BTreeMap, fs::read_to_string, path::Path};
+
+use anyhow::{Context, Result};
+use minijinja::Value;
+use walkdir::DirEntry;
+
+use crate::{context::find_layered_files, paths::Paths, utils::extension};
+
+fn is_data_file(entry: &DirEntry) -> bool {
+    entry
+        .path()
+        .extension()
+        .is_some_and(|e| ["toml", "json"].contains(&e.to_string_lossy().as_ref()))
+}
+
+/// Every TOML and JSON file in `data/` by its path without the extension,
+/// like `data.authors` for `data/authors.toml`.
+pub fn load(paths: &Paths) -> Result<Value> {
+    let mut data = BTreeMap::new();
+    for (dir, path) in find_layered_files(paths, |p| &p.data, is_data_file) {
+        let value = parse(&path).with_context(|| format!("Could not load {}", path.display()))?;
+        let key = path
+            .strip_prefix(&dir)?
+            .with_extension("")
+            .to_string_lossy()
+            .replace('\\', "/");
+        data.insert(key, value);
+    }
+
+    Ok(Value::from(data))
+}
+
+fn parse(path: &Path) -> Result<Value> {
+    let source = read_to_string(path)?;
+    if extension(path) == "toml" {
+        Ok(Value::from_serialize(toml::from_str::<toml::Table>(
+            &source,
+        )?))
+    } else {
+        Ok(Value::from_serialize(serde_json::from_str::<
+            serde_json::Value,
+        >(&source)?))
+    }
+}
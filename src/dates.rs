@@ -0,0 +1,154 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    time::SystemTime,
+};
+
+use dashmap::DashMap;
+use jiff::{Timestamp, civil::Date, tz::TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::frontmatter::Frontmatter;
+
+/// Where to find `created` and `last_modified` dates missing from the
+/// frontmatter.
+#[derive(Debug, Copy, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DateSource {
+    #[default]
+    Frontmatter,
+    Git,
+}
+
+/// The dates found for each file with its modification time, so files that
+/// did not change are not looked up again when `dev` reads every page again.
+#[derive(Debug)]
+pub struct Dates {
+    source: DateSource,
+    found: DashMap<PathBuf, (SystemTime, Option<(Date, Date)>)>,
+}
+
+impl Dates {
+    pub fn new(source: DateSource) -> Self {
+        Dates {
+            source,
+            found: DashMap::new(),
+        }
+    }
+
+    /// Fills in missing dates from the first and last commit touching `path`,
+    /// falling back to the file modification time when it is not tracked by
+    /// git.
+    pub fn fill(&self, path: &Path, frontmatter: &mut Frontmatter) {
+        if matches!(self.source, DateSource::Frontmatter)
+            || (frontmatter.created.is_some() && frontmatter.last_modified.is_some())
+        {
+            return;
+        }
+
+        let Some((created, last_modified)) = self.find(path) else {
+            tracing::warn!("Could not find dates for {}", path.display());
+            return;
+        };
+
+        frontmatter.created.get_or_insert(created);
+        frontmatter.last_modified.get_or_insert(last_modified);
+    }
+
+    fn find(&self, path: &Path) -> Option<(Date, Date)> {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let Some(found) = self.found.get(path)
+            && Some(found.0) == modified
+        {
+            return found.1;
+        }
+
+        let dates = git_dates(path).or_else(|| file_dates(path));
+        if let Some(modified) = modified {
+            self.found.insert(path.to_path_buf(), (modified, dates));
+        }
+        dates
+    }
+}
+
+fn git_dates(path: &Path) -> Option<(Date, Date)> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path.parent()?)
+        .args(["log", "--follow", "--format=%ad", "--date=short", "--"])
+        .arg(path.file_name()?)
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    parse_log(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn file_dates(path: &Path) -> Option<(Date, Date)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let to_date = |time| {
+        Timestamp::try_from(time)
+            .ok()
+            .map(|t| t.to_zoned(TimeZone::system()).date())
+    };
+
+    let last_modified = to_date(metadata.modified().ok()?)?;
+    let created = metadata
+        .created()
+        .ok()
+        .and_then(to_date)
+        .unwrap_or(last_modified);
+
+    Some((created, last_modified))
+}
+
+/// Parses `git log` output with one date per line, newest first, into the
+/// first and last date the file was changed.
+fn parse_log(log: &str) -> Option<(Date, Date)> {
+    let mut dates = log.lines().filter_map(|l| l.trim().parse::<Date>().ok());
+    let last_modified = dates.next()?;
+    let created = dates.next_back().unwrap_or(last_modified);
+
+    Some((created, last_modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::civil::date;
+
+    use super::{DateSource, Dates, parse_log};
+    use crate::{frontmatter::Frontmatter, utils::TestDir};
+
+    #[test]
+    fn test_parse_log() {
+        let log = "2025-03-01\n2024-06-12\n2024-01-05\n";
+        assert_eq!(parse_log(log), Some((date(2024, 1, 5), date(2025, 3, 1))));
+        assert_eq!(
+            parse_log("2024-01-05\n"),
+            Some((date(2024, 1, 5), date(2024, 1, 5)))
+        );
+        assert_eq!(parse_log(""), None);
+    }
+
+    #[test]
+    fn test_dates_found_once() {
+        let dir = TestDir::new("dates");
+        let path = dir.path().join("page.kladd");
+        std::fs::write(&path, "Hello").unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let dates = Dates::new(DateSource::Git);
+        let found = (date(2024, 1, 5), date(2025, 3, 1));
+        dates.found.insert(path.clone(), (modified, Some(found)));
+
+        let mut frontmatter = Frontmatter::empty();
+        dates.fill(&path, &mut frontmatter);
+        assert_eq!(
+            (frontmatter.created, frontmatter.last_modified),
+            (Some(found.0), Some(found.1))
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::{error::Error as _, fmt};
+
+use minijinja::ErrorKind;
+use serde::Serialize;
+
+/// Lines of source shown before and after the line with the error.
+const CONTEXT_LINES: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Location {
+    pub template: String,
+    pub line: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SnippetLine {
+    pub number: usize,
+    pub text: String,
+}
+
+/// A template error with the template, line and source around where it
+/// happened, the templates that included it and the page being rendered.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub page: String,
+    pub template: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// The number of characters to highlight from `column`.
+    pub width: usize,
+    pub snippet: Vec<SnippetLine>,
+    /// Where the failing template was included from, outermost first.
+    pub included_from: Vec<Location>,
+}
+
+impl Diagnostic {
+    /// Builds a diagnostic from the innermost template error in `err`, using
+    /// `source` to load templates errors carry no source for, like syntax
+    /// errors.
+    pub fn new(
+        err: &anyhow::Error,
+        page: &str,
+        source: impl Fn(&str) -> Option<String>,
+    ) -> Option<Self> {
+        let mut error = err
+            .chain()
+            .find_map(|e| e.downcast_ref::<minijinja::Error>())?;
+
+        let mut included_from = Vec::new();
+        while error.kind() == ErrorKind::BadInclude
+            && let Some(inner) = error
+                .source()
+                .and_then(|e| e.downcast_ref::<minijinja::Error>())
+        {
+            if let Some(name) = error.name() {
+                included_from.push(Location {
+                    template: name.to_string(),
+                    line: error.line(),
+                });
+            }
+            error = inner;
+        }
+
+        let message = match error.detail() {
+            Some(detail) => format!("{}: {detail}", error.kind()),
+            None => error.kind().to_string(),
+        };
+        let source = error
+            .template_source()
+            .map(ToString::to_string)
+            .or_else(|| error.name().and_then(&source));
+
+        let mut line = error.line();
+        let (mut column, mut width) = (None, 1);
+        if let (Some(range), Some(source)) = (error.range(), &source)
+            && let Some(before) = source.get(..range.start)
+        {
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            line = Some(before.matches('\n').count() + 1);
+            column = Some(before[line_start..].chars().count() + 1);
+            width = source
+                .get(range)
+                .and_then(|s| s.lines().next())
+                .map_or(1, |s| s.chars().count().max(1));
+        }
+
+        let snippet = match (line, &source) {
+            (Some(line), Some(source)) => source
+                .lines()
+                .enumerate()
+                .map(|(i, text)| SnippetLine {
+                    number: i + 1,
+                    text: text.to_string(),
+                })
+                .skip(line.saturating_sub(CONTEXT_LINES + 1))
+                .take(CONTEXT_LINES * 2 + 1)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Some(Diagnostic {
+            message,
+            page: page.to_string(),
+            template: error.name().map(ToString::to_string),
+            line,
+            column,
+            width,
+            snippet,
+            included_from,
+        })
+    }
+
+    /// Replaces the names templates are loaded by, like `_base.html`, with
+    /// something easier to find, like their path.
+    pub fn rename(mut self, name: impl Fn(&str) -> String) -> Self {
+        self.template = self.template.as_deref().map(&name);
+        for include in &mut self.included_from {
+            include.template = name(&include.template);
+        }
+        self
+    }
+}
+
+fn location(name: &str, line: Option<usize>, column: Option<usize>) -> String {
+    match (line, column) {
+        (Some(line), Some(column)) => format!("{name}:{line}:{column}"),
+        (Some(line), None) => format!("{name}:{line}"),
+        _ => name.to_string(),
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.message)?;
+
+        let gutter = self
+            .snippet
+            .last()
+            .map_or(1, |l| l.number.to_string().len());
+        if let Some(template) = &self.template {
+            writeln!(
+                f,
+                "{:gutter$}--> {}",
+                "",
+                location(template, self.line, self.column)
+            )?;
+        }
+
+        if !self.snippet.is_empty() {
+            writeln!(f, "{:gutter$} |", "")?;
+            for line in &self.snippet {
+                writeln!(f, "{:>gutter$} | {}", line.number, line.text)?;
+                if Some(line.number) == self.line
+                    && let Some(column) = self.column
+                {
+                    let marker = "^".repeat(self.width);
+                    writeln!(
+                        f,
+                        "{:gutter$} | {:>width$}",
+                        "",
+                        marker,
+                        width = column - 1 + self.width
+                    )?;
+                }
+            }
+            writeln!(f, "{:gutter$} |", "")?;
+        }
+
+        for include in self.included_from.iter().rev() {
+            let location = location(&include.template, include.line, None);
+            writeln!(f, "{:gutter$} = included from {location}", "")?;
+        }
+        write!(f, "{:gutter$} = while rendering {}", "", self.page)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use minijinja::{Environment, context};
+
+    use super::{Diagnostic, Location};
+
+    #[test]
+    fn test_diagnostic() {
+        let mut env = Environment::new();
+        env.set_debug(true);
+        env.add_template("base.html", "<body>\n{% include 'nav.html' %}\n</body>")
+            .unwrap();
+        env.add_template("nav.html", "<nav>\n  {{ menu.items }}\n</nav>")
+            .unwrap();
+
+        let err = env
+            .get_template("base.html")
+            .unwrap()
+            .render(context! {})
+            .context("Could not render")
+            .unwrap_err();
+        let diagnostic = Diagnostic::new(&err, "content/index.kladd", |_| None).unwrap();
+
+        assert_eq!(diagnostic.template.as_deref(), Some("nav.html"));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(2), Some(6)));
+        assert_eq!(
+            diagnostic.included_from,
+            vec![Location {
+                template: "base.html".to_string(),
+                line: Some(2)
+            }]
+        );
+        let expected = [
+            "undefined value",
+            " --> nav.html:2:6",
+            "  |",
+            "1 | <nav>",
+            "2 |   {{ menu.items }}",
+            "  |      ^^^^^^^^^^",
+            "3 | </nav>",
+            "  |",
+            "  = included from base.html:2",
+            "  = while rendering content/index.kladd",
+        ];
+        assert_eq!(diagnostic.to_string(), expected.join("\n"));
+    }
+}
@@ -1,8 +1,10 @@
 use flume::{Receiver, Sender};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum Event {
     Reload,
+    /// A failed rebuild, shown in the browser until the next reload.
+    Error(String),
 }
 
 pub fn events() -> (Sender<Event>, Receiver<Event>) {
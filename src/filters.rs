@@ -0,0 +1,579 @@
+//! Filters registered in every template environment, on top of the ones from
+//! `minijinja_contrib`:
+//!
+//! - `slugify`: `{{ title | slugify }}` turns "Hello, World!" into `hello-world`
+//! - `absolute_url`: `{{ "/feed.xml" | absolute_url }}` joins a path with the
+//!   site URL
+//! - `markdown`: `{{ description | markdown(inline=true) }}` renders Markdown,
+//!   `inline` drops the paragraph around a single line
+//! - `date_format`: `{{ created | date_format("%-d. %B %Y", locale="nb",
+//!   timezone="Europe/Oslo") }}` formats dates, times and timestamps with
+//!   `strftime` and localised month and day names
+//! - `truncate_html`: `{{ content | truncate_html(200) }}` shortens HTML to a
+//!   number of visible characters and closes the tags left open
+//! - `json_ld`: `{{ {"@type": "Article", "headline": title} | json_ld }}`
+//!   writes a JSON-LD `<script>` with the schema.org context
+//! - `where`: `{{ pages | where("taxonomies.tags", "rust") }}` keeps items
+//!   where an attribute, or a list in it, matches a value, or is truthy
+//!   without one
+//! - `sort_by`: `{{ pages | sort_by("created", reverse=true) }}` sorts items
+//!   by an attribute
+
+use jiff::{
+    Timestamp, Zoned,
+    civil::{Date, DateTime},
+    tz::TimeZone,
+};
+use minijinja::{
+    Environment, Error, ErrorKind, State, Value,
+    value::{Kwargs, ValueKind},
+};
+use pulldown_cmark::{Options, Parser, html::push_html};
+use url::Url;
+
+use crate::utils::strip_html;
+
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+pub fn register(env: &mut Environment) {
+    env.add_filter("slugify", slugify);
+    env.add_filter("absolute_url", absolute_url);
+    env.add_filter("markdown", markdown);
+    env.add_filter("date_format", date_format);
+    env.add_filter("truncate_html", truncate_html);
+    env.add_filter("json_ld", json_ld);
+    env.add_filter("where", r#where);
+    env.add_filter("sort_by", sort_by);
+}
+
+fn slugify(value: &str) -> String {
+    value
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn absolute_url(state: &State, path: &str) -> Result<String, Error> {
+    let base = state
+        .lookup("config")
+        .and_then(|c| c.get_attr("url").ok())
+        .filter(|url| !url.is_undefined())
+        .ok_or_else(|| invalid("absolute_url needs the site config"))?;
+    let base = Url::parse(&base.to_string()).map_err(|err| invalid(err.to_string()))?;
+
+    base.join(path.trim_start_matches('/'))
+        .map(|url| url.to_string())
+        .map_err(|err| invalid(err.to_string()))
+}
+
+fn markdown(value: &str, kwargs: Kwargs) -> Result<Value, Error> {
+    let inline: Option<bool> = kwargs.get("inline")?;
+    kwargs.assert_all_used()?;
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_SMART_PUNCTUATION;
+    let mut html = String::new();
+    push_html(&mut html, Parser::new_ext(value, options));
+
+    let html = html.trim_end();
+    let html = if inline.unwrap_or(false) {
+        html.strip_prefix("<p>")
+            .and_then(|h| h.strip_suffix("</p>"))
+            .filter(|h| !h.contains("<p>"))
+            .unwrap_or(html)
+    } else {
+        html
+    };
+
+    Ok(Value::from_safe_string(html.to_string()))
+}
+
+fn date_format(value: &Value, format: Option<&str>, kwargs: Kwargs) -> Result<String, Error> {
+    let locale: Option<&str> = kwargs.get("locale")?;
+    let timezone: Option<&str> = kwargs.get("timezone")?;
+    kwargs.assert_all_used()?;
+
+    let tz = match timezone {
+        Some(name) => TimeZone::get(name).map_err(|err| invalid(err.to_string()))?,
+        None => TimeZone::UTC,
+    };
+    let names = Names::for_locale(locale.unwrap_or("en"))?;
+    let date = parse_date(value, &tz)?;
+
+    Ok(date
+        .strftime(&names.localize(format.unwrap_or("%B %-d, %Y"), &date))
+        .to_string())
+}
+
+fn truncate_html(html: &str, length: usize, end: Option<&str>) -> Value {
+    let mut out = String::with_capacity(html.len());
+    let mut open: Vec<String> = Vec::new();
+    let mut count = 0;
+    let mut chars = html.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        // At the limit nothing new is opened, the rest is only kept when it has
+        // no more text
+        if count == length {
+            if strip_html(&html[i..]).trim().is_empty() {
+                out.push_str(&html[i..]);
+            } else {
+                out.push_str(end.unwrap_or("…"));
+                for tag in open.iter().rev() {
+                    out.push_str(&format!("</{tag}>"));
+                }
+            }
+            return Value::from_safe_string(out);
+        }
+
+        match c {
+            '<' => {
+                let len = html[i..].find('>').map_or(html.len() - i, |l| l + 1);
+                let tag = &html[i..i + len];
+                let name = tag
+                    .trim_start_matches(['<', '/'])
+                    .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_lowercase();
+
+                if tag.starts_with("</") {
+                    if let Some(pos) = open.iter().rposition(|t| *t == name) {
+                        open.truncate(pos);
+                    }
+                } else if !tag.starts_with("<!")
+                    && !tag.ends_with("/>")
+                    && !VOID_ELEMENTS.contains(&name.as_str())
+                {
+                    open.push(name);
+                }
+
+                out.push_str(tag);
+                while chars.peek().is_some_and(|(j, _)| *j < i + len) {
+                    chars.next();
+                }
+            }
+            '&' => {
+                let len = html[i..].find(';').filter(|l| *l < 10).map_or(1, |l| l + 1);
+                out.push_str(&html[i..i + len]);
+                while chars.peek().is_some_and(|(j, _)| *j < i + len) {
+                    chars.next();
+                }
+                count += 1;
+            }
+            c => {
+                out.push(c);
+                count += 1;
+            }
+        }
+    }
+
+    Value::from_safe_string(out)
+}
+
+fn json_ld(value: &Value) -> Result<Value, Error> {
+    let mut json = serde_json::to_value(value).map_err(|err| invalid(err.to_string()))?;
+    if let Some(object) = json.as_object_mut() {
+        object
+            .entry("@context")
+            .or_insert_with(|| "https://schema.org".into());
+    }
+
+    let json = json.to_string().replace("</", "<\\/");
+    Ok(Value::from_safe_string(format!(
+        r#"<script type="application/ld+json">{json}</script>"#
+    )))
+}
+
+fn r#where(items: Vec<Value>, attribute: &str, value: Option<Value>) -> Vec<Value> {
+    items
+        .into_iter()
+        .filter(|item| {
+            let found = lookup(item, attribute);
+            match &value {
+                None => found.is_true(),
+                Some(value) => {
+                    found == *value
+                        || (found.kind() == ValueKind::Seq
+                            && found.try_iter().is_ok_and(|mut i| i.any(|v| v == *value)))
+                }
+            }
+        })
+        .collect()
+}
+
+fn sort_by(items: Vec<Value>, attribute: &str, kwargs: Kwargs) -> Result<Vec<Value>, Error> {
+    let reverse: Option<bool> = kwargs.get("reverse")?;
+    kwargs.assert_all_used()?;
+
+    let mut items = items;
+    items.sort_by_cached_key(|item| lookup(item, attribute));
+    if reverse.unwrap_or(false) {
+        items.reverse();
+    }
+
+    Ok(items)
+}
+
+/// Looks up a dotted path like `taxonomies.tags` in a value.
+fn lookup(value: &Value, path: &str) -> Value {
+    path.split('.').fold(value.clone(), |value, key| {
+        value.get_attr(key).unwrap_or(Value::UNDEFINED)
+    })
+}
+
+fn parse_date(value: &Value, tz: &TimeZone) -> Result<Zoned, Error> {
+    if let Some(seconds) = value.as_i64() {
+        return Timestamp::from_second(seconds)
+            .map(|t| t.to_zoned(tz.clone()))
+            .map_err(|err| invalid(err.to_string()));
+    }
+
+    let text = value
+        .as_str()
+        .ok_or_else(|| invalid(format!("Cannot format {value} as a date")))?;
+
+    if let Ok(zoned) = text.parse::<Zoned>() {
+        Ok(zoned.with_time_zone(tz.clone()))
+    } else if let Ok(timestamp) = text.parse::<Timestamp>() {
+        Ok(timestamp.to_zoned(tz.clone()))
+    } else if let Ok(datetime) = text.parse::<DateTime>() {
+        datetime
+            .to_zoned(tz.clone())
+            .map_err(|err| invalid(err.to_string()))
+    } else {
+        text.parse::<Date>()
+            .and_then(|date| date.to_zoned(tz.clone()))
+            .map_err(|_| invalid(format!("Cannot format {text} as a date")))
+    }
+}
+
+/// Month and weekday names, Monday first.
+struct Names {
+    months: [&'static str; 12],
+    short_months: [&'static str; 12],
+    days: [&'static str; 7],
+    short_days: [&'static str; 7],
+}
+
+impl Names {
+    fn for_locale(locale: &str) -> Result<Self, Error> {
+        let language = locale.split(['-', '_']).next().unwrap_or_default();
+
+        Ok(match language {
+            "en" => Names {
+                months: [
+                    "January",
+                    "February",
+                    "March",
+                    "April",
+                    "May",
+                    "June",
+                    "July",
+                    "August",
+                    "September",
+                    "October",
+                    "November",
+                    "December",
+                ],
+                short_months: [
+                    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov",
+                    "Dec",
+                ],
+                days: [
+                    "Monday",
+                    "Tuesday",
+                    "Wednesday",
+                    "Thursday",
+                    "Friday",
+                    "Saturday",
+                    "Sunday",
+                ],
+                short_days: ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+            },
+            "nb" | "no" | "nn" => Names {
+                months: [
+                    "januar",
+                    "februar",
+                    "mars",
+                    "april",
+                    "mai",
+                    "juni",
+                    "juli",
+                    "august",
+                    "september",
+                    "oktober",
+                    "november",
+                    "desember",
+                ],
+                short_months: [
+                    "jan", "feb", "mar", "apr", "mai", "jun", "jul", "aug", "sep", "okt", "nov",
+                    "des",
+                ],
+                days: [
+                    "mandag", "tirsdag", "onsdag", "torsdag", "fredag", "lørdag", "søndag",
+                ],
+                short_days: ["man", "tir", "ons", "tor", "fre", "lør", "søn"],
+            },
+            "sv" => Names {
+                months: [
+                    "januari",
+                    "februari",
+                    "mars",
+                    "april",
+                    "maj",
+                    "juni",
+                    "juli",
+                    "augusti",
+                    "september",
+                    "oktober",
+                    "november",
+                    "december",
+                ],
+                short_months: [
+                    "jan", "feb", "mar", "apr", "maj", "jun", "jul", "aug", "sep", "okt", "nov",
+                    "dec",
+                ],
+                days: [
+                    "måndag", "tisdag", "onsdag", "torsdag", "fredag", "lördag", "söndag",
+                ],
+                short_days: ["mån", "tis", "ons", "tors", "fre", "lör", "sön"],
+            },
+            "da" => Names {
+                months: [
+                    "januar",
+                    "februar",
+                    "marts",
+                    "april",
+                    "maj",
+                    "juni",
+                    "juli",
+                    "august",
+                    "september",
+                    "oktober",
+                    "november",
+                    "december",
+                ],
+                short_months: [
+                    "jan", "feb", "mar", "apr", "maj", "jun", "jul", "aug", "sep", "okt", "nov",
+                    "dec",
+                ],
+                days: [
+                    "mandag", "tirsdag", "onsdag", "torsdag", "fredag", "lørdag", "søndag",
+                ],
+                short_days: ["man", "tir", "ons", "tor", "fre", "lør", "søn"],
+            },
+            "de" => Names {
+                months: [
+                    "Januar",
+                    "Februar",
+                    "März",
+                    "April",
+                    "Mai",
+                    "Juni",
+                    "Juli",
+                    "August",
+                    "September",
+                    "Oktober",
+                    "November",
+                    "Dezember",
+                ],
+                short_months: [
+                    "Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov",
+                    "Dez",
+                ],
+                days: [
+                    "Montag",
+                    "Dienstag",
+                    "Mittwoch",
+                    "Donnerstag",
+                    "Freitag",
+                    "Samstag",
+                    "Sonntag",
+                ],
+                short_days: ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"],
+            },
+            "fr" => Names {
+                months: [
+                    "janvier",
+                    "février",
+                    "mars",
+                    "avril",
+                    "mai",
+                    "juin",
+                    "juillet",
+                    "août",
+                    "septembre",
+                    "octobre",
+                    "novembre",
+                    "décembre",
+                ],
+                short_months: [
+                    "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.",
+                    "oct.", "nov.", "déc.",
+                ],
+                days: [
+                    "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche",
+                ],
+                short_days: ["lun.", "mar.", "mer.", "jeu.", "ven.", "sam.", "dim."],
+            },
+            _ => {
+                return Err(invalid(format!(
+                    "Unsupported locale {locale}, expected one of en, nb, sv, da, de or fr"
+                )));
+            }
+        })
+    }
+
+    /// Replaces the month and day names in a `strftime` format, since jiff
+    /// only knows them in English.
+    fn localize(&self, format: &str, date: &Zoned) -> String {
+        let month = usize::try_from(date.month() - 1).unwrap_or_default();
+        let day = usize::try_from(date.weekday().to_monday_zero_offset()).unwrap_or_default();
+
+        let mut out = String::with_capacity(format.len());
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('B') => out.push_str(self.months[month]),
+                Some('b' | 'h') => out.push_str(self.short_months[month]),
+                Some('A') => out.push_str(self.days[day]),
+                Some('a') => out.push_str(self.short_days[day]),
+                Some(other) => {
+                    out.push('%');
+                    out.push(other);
+                }
+                None => out.push('%'),
+            }
+        }
+
+        out
+    }
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidOperation, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use minijinja::{Environment, context};
+
+    use super::register;
+
+    fn render(template: &str) -> String {
+        let mut env = Environment::new();
+        register(&mut env);
+        let pages = vec![
+            context! { title => "B", created => "2024-02-01", taxonomies => context! { tags => vec!["rust"] } },
+            context! { title => "A", created => "2024-03-01", taxonomies => context! { tags => vec!["food"] } },
+            context! { title => "C", created => "2024-01-01", draft => true },
+        ];
+        let config = context! { url => "https://example.org/blog/" };
+
+        env.render_str(template, context! { pages, config })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_slugify_and_absolute_url() {
+        assert_eq!(
+            render(r#"{{ "Hello, World! Æøå" | slugify }}"#),
+            "hello-world-æøå"
+        );
+        assert_eq!(
+            render(r#"{{ "/feed.xml" | absolute_url }}"#),
+            "https://example.org/blog/feed.xml"
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        assert_eq!(
+            render(r#"{{ "Some *text*" | markdown }}"#),
+            "<p>Some <em>text</em></p>"
+        );
+        assert_eq!(
+            render(r#"{{ "Some *text*" | markdown(inline=true) }}"#),
+            "Some <em>text</em>"
+        );
+    }
+
+    #[test]
+    fn test_date_format() {
+        assert_eq!(
+            render(r#"{{ "2024-05-17" | date_format("%A %-d. %B %Y", locale="nb") }}"#),
+            "fredag 17. mai 2024"
+        );
+        assert_eq!(
+            render(
+                r#"{{ "2024-05-17T22:30:00Z" | date_format("%Y-%m-%d %H:%M", timezone="Europe/Oslo") }}"#
+            ),
+            "2024-05-18 00:30"
+        );
+        assert_eq!(
+            render(r#"{{ "2024-05-17" | date_format }}"#),
+            "May 17, 2024"
+        );
+    }
+
+    #[test]
+    fn test_truncate_html() {
+        assert_eq!(
+            render(r#"{{ "<p>Hello <em>wide</em> world</p><p>More</p>" | truncate_html(8) }}"#),
+            "<p>Hello <em>wi…</em></p>"
+        );
+        assert_eq!(
+            render(r#"{{ "<p>Tom &amp; Jerry<br></p>" | truncate_html(20) }}"#),
+            "<p>Tom &amp; Jerry<br></p>"
+        );
+        assert_eq!(
+            render(r#"{{ "<p>Hello</p><p>More</p>" | truncate_html(5) }}"#),
+            "<p>Hello…</p>"
+        );
+        assert_eq!(
+            render(r#"{{ "<p>Hello</p>\n<hr>" | truncate_html(5) }}"#),
+            "<p>Hello</p>\n<hr>"
+        );
+    }
+
+    #[test]
+    fn test_json_ld() {
+        assert_eq!(
+            render(r#"{{ {"@type": "Article", "headline": "</script>"} | json_ld }}"#),
+            r#"<script type="application/ld+json">{"@context":"https://schema.org","@type":"Article","headline":"<\/script>"}</script>"#
+        );
+    }
+
+    #[test]
+    fn test_where_and_sort_by() {
+        assert_eq!(
+            render(
+                r#"{% for p in pages | where("taxonomies.tags", "rust") %}{{ p.title }}{% endfor %}"#
+            ),
+            "B"
+        );
+        assert_eq!(
+            render(r#"{% for p in pages | where("draft") %}{{ p.title }}{% endfor %}"#),
+            "C"
+        );
+        assert_eq!(
+            render(
+                r#"{% for p in pages | sort_by("created", reverse=true) %}{{ p.title }}{% endfor %}"#
+            ),
+            "ABC"
+        );
+    }
+}
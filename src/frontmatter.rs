@@ -1,21 +1,62 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
 use anyhow::{Context, Result};
 use jiff::civil::Date;
 use minijinja::{Value, context};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use crate::utils::toml_date_jiff_serde;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// When to run a content body through the template engine, `template = true`
+/// is the same as `template = "before"`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateStage {
+    #[default]
+    Never,
+    Before,
+    After,
+}
+
+impl TemplateStage {
+    fn deserialize<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stage {
+            Enabled(bool),
+            Stage(TemplateStage),
+        }
+
+        Ok(match Stage::deserialize(deserializer)? {
+            Stage::Enabled(true) => TemplateStage::Before,
+            Stage::Enabled(false) => TemplateStage::Never,
+            Stage::Stage(stage) => stage,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Frontmatter {
     pub title: String,
     pub subtitle: Option<String>,
     pub description: Option<String>,
     pub slug: Option<String>,
+    pub path: Option<String>,
     pub layout: Option<String>,
+    pub outputs: Option<Vec<String>>,
+    pub search: Option<bool>,
+    pub bibliography: Option<PathBuf>,
     #[serde(with = "toml_date_jiff_serde", default)]
     pub last_modified: Option<Date>,
     #[serde(with = "toml_date_jiff_serde", default)]
     pub created: Option<Date>,
+    #[serde(default)]
+    pub taxonomies: BTreeMap<String, Vec<String>>,
+    #[serde(default, deserialize_with = "TemplateStage::deserialize")]
+    pub template: TemplateStage,
 }
 
 impl Frontmatter {
@@ -25,9 +66,15 @@ impl Frontmatter {
             subtitle: None,
             description: None,
             slug: None,
+            path: None,
             layout: None,
+            outputs: None,
+            search: None,
+            bibliography: None,
             last_modified: None,
             created: None,
+            taxonomies: BTreeMap::new(),
+            template: TemplateStage::Never,
         }
     }
 
@@ -35,11 +82,6 @@ impl Frontmatter {
         toml::from_str(input).context("Could not parse frontmatter")
     }
 
-    pub fn url(&self, stem: &str) -> String {
-        let url = self.slug.as_ref().map_or(stem, |s| s).to_string();
-        format!("{url}/")
-    }
-
     pub fn to_context(&self) -> Value {
         context! {
             title => &self.title,
@@ -47,7 +89,29 @@ impl Frontmatter {
             description => &self.description,
             slug => &self.slug,
             last_modified => &self.last_modified,
-            created => &self.created
+            created => &self.created,
+            taxonomies => &self.taxonomies
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frontmatter, TemplateStage};
+
+    #[test]
+    fn test_template_stage() {
+        let stages = [
+            ("", TemplateStage::Never),
+            ("template = false", TemplateStage::Never),
+            ("template = true", TemplateStage::Before),
+            ("template = \"before\"", TemplateStage::Before),
+            ("template = \"after\"", TemplateStage::After),
+        ];
+
+        for (input, stage) in stages {
+            let frontmatter = Frontmatter::deserialize(&format!("title = \"Test\"\n{input}"));
+            assert_eq!(frontmatter.unwrap().template, stage);
         }
     }
 }
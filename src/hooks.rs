@@ -0,0 +1,160 @@
+use std::{
+    fmt::Display,
+    io::{BufRead, BufReader, Read},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{build_mode::BuildMode, paths::Paths};
+
+/// Shell commands run in the site root around every build, with the output
+/// directory in `STATISK_OUT_DIR` and the build mode in `STATISK_MODE`.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    pub pre_build: Vec<String>,
+    pub post_render: Vec<String>,
+    pub post_build: Vec<String>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Stage {
+    /// Before anything is collected, so generated files are picked up. In dev
+    /// mode every page is read again after it.
+    PreBuild,
+    /// After all pages are written.
+    PostRender,
+    /// After the whole build, including compressing the output.
+    PostBuild,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::PreBuild => write!(f, "pre_build"),
+            Stage::PostRender => write!(f, "post_render"),
+            Stage::PostBuild => write!(f, "post_build"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Hooks {
+    config: HooksConfig,
+    root: PathBuf,
+    out: PathBuf,
+    mode: BuildMode,
+}
+
+impl Hooks {
+    pub fn new(paths: &Paths, mode: BuildMode, config: HooksConfig) -> Self {
+        Hooks {
+            config,
+            root: paths.root.clone(),
+            out: paths.out.clone(),
+            mode,
+        }
+    }
+
+    /// Runs the commands for `stage` in order, failing on the first one that
+    /// exits with an error.
+    pub fn run(&self, stage: Stage) -> Result<()> {
+        let commands = match stage {
+            Stage::PreBuild => &self.config.pre_build,
+            Stage::PostRender => &self.config.post_render,
+            Stage::PostBuild => &self.config.post_build,
+        };
+
+        commands
+            .iter()
+            .try_for_each(|command| self.run_command(stage, command))
+    }
+
+    fn run_command(&self, stage: Stage, command: &str) -> Result<()> {
+        tracing::info!("Running {stage} hook `{command}`");
+        let mut child = shell(command)
+            .current_dir(&self.root)
+            .env("STATISK_ROOT", &self.root)
+            .env("STATISK_OUT_DIR", &self.out)
+            .env("STATISK_MODE", self.mode.to_string())
+            .env("STATISK_HOOK", stage.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Could not run {stage} hook `{command}`"))?;
+
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        thread::scope(|scope| {
+            if let Some(stdout) = stdout {
+                scope.spawn(move || log_lines(stage, stdout, false));
+            }
+            if let Some(stderr) = stderr {
+                scope.spawn(move || log_lines(stage, stderr, true));
+            }
+        });
+
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("The {stage} hook `{command}` failed with {status}");
+        }
+
+        Ok(())
+    }
+}
+
+fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+fn log_lines(stage: Stage, output: impl Read, is_stderr: bool) {
+    for line in BufReader::new(output).lines().map_while(Result::ok) {
+        if is_stderr {
+            tracing::warn!("[{stage}] {line}");
+        } else {
+            tracing::info!("[{stage}] {line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hooks, HooksConfig, Stage};
+    use crate::{build_mode::BuildMode, paths::Paths};
+
+    #[test]
+    fn test_run() {
+        let config = HooksConfig {
+            pre_build: vec!["echo first".to_string(), "exit 0".to_string()],
+            post_render: Vec::new(),
+            post_build: vec!["exit 3".to_string(), "echo never".to_string()],
+        };
+        let hooks = Hooks::new(
+            &Paths::new(&std::env::temp_dir()),
+            BuildMode::Normal,
+            config,
+        );
+
+        assert!(hooks.run(Stage::PreBuild).is_ok());
+        assert!(hooks.run(Stage::PostRender).is_ok());
+        let err = hooks.run(Stage::PostBuild).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("The post_build hook `exit 3` failed")
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result, bail};
+use dashmap::DashMap;
+use image::{
+    DynamicImage, ImageDecoder, ImageFormat, ImageReader,
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder, png::PngEncoder},
+    imageops::FilterType,
+};
+use minijinja::{Error, ErrorKind, Value, value::Kwargs};
+use serde::Serialize;
+use sha1_smol::Sha1;
+
+use crate::{
+    paths::Paths,
+    utils::{append_extension, write_file},
+};
+
+pub const IMAGE_DIR: &str = "images";
+const DEFAULT_FORMATS: [&str; 2] = ["avif", "webp"];
+const QUALITY: u8 = 80;
+const AVIF_SPEED: u8 = 8;
+/// Part of every fingerprint, bump it when changing how images are encoded
+/// to invalidate the cache.
+const VERSION: &str = "1";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Source {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub srcset: String,
+}
+
+/// Everything needed for a `<picture>` element: a `<source>` for each modern
+/// format and an `<img>` in the original format with its intrinsic size.
+#[derive(Debug, Clone, Serialize)]
+pub struct Picture {
+    pub src: String,
+    pub srcset: String,
+    pub width: u32,
+    pub height: u32,
+    pub sources: Vec<Source>,
+}
+
+/// Resizes and converts images from `public/` of the site or its theme into
+/// `_dist/images/`, keeping every encoded file in a cache so unchanged images
+/// are only copied on the next build.
+#[derive(Debug)]
+pub struct Images {
+    public: Vec<PathBuf>,
+    out: PathBuf,
+    cache: PathBuf,
+    processed: DashMap<String, Picture>,
+}
+
+impl Images {
+    pub fn new(paths: &Paths) -> Self {
+        Images {
+            public: paths.layers().map(|p| p.public.clone()).collect(),
+            out: paths.out.clone(),
+            cache: paths.cache.join(IMAGE_DIR),
+            processed: DashMap::new(),
+        }
+    }
+
+    /// The `image(path, widths=[...], formats=[...])` template function.
+    pub fn function(&self, path: &str, kwargs: Kwargs) -> Result<Value, Error> {
+        let widths: Option<Vec<u32>> = kwargs.get("widths")?;
+        let formats: Option<Vec<String>> = kwargs.get("formats")?;
+        kwargs.assert_all_used()?;
+
+        let formats =
+            formats.unwrap_or_else(|| DEFAULT_FORMATS.iter().map(ToString::to_string).collect());
+        let picture = self
+            .process(path, widths.as_deref().unwrap_or_default(), &formats)
+            .map_err(|err| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("Could not process image {path}: {err:#}"),
+                )
+            })?;
+
+        Ok(Value::from_serialize(&picture))
+    }
+
+    /// Resizes `path` to each of `widths` without upscaling, or keeps its
+    /// size if there are none, and encodes every size in each of `formats`
+    /// and in the original format as a fallback.
+    pub fn process(&self, path: &str, widths: &[u32], formats: &[String]) -> Result<Picture> {
+        // The processed images are written by the same path in `images/`
+        let path = path.trim_start_matches('/');
+        if !Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            bail!("Invalid path {path:?}, it cannot contain `..` or be absolute");
+        }
+
+        let source = self
+            .public
+            .iter()
+            .map(|dir| dir.join(path))
+            .find(|file| file.is_file())
+            .with_context(|| format!("Could not find {path} in public/"))?;
+        let bytes = std::fs::read(&source)
+            .with_context(|| format!("Could not read {}", source.display()))?;
+
+        let mut digest = Sha1::from(&bytes);
+        digest.update(VERSION.as_bytes());
+        let hash = digest.hexdigest()[..8].to_string();
+
+        let key = format!("{path}:{hash}:{widths:?}:{formats:?}");
+        if let Some(picture) = self.processed.get(&key) {
+            return Ok(picture.clone());
+        }
+
+        let image = decode(&bytes)?;
+        let fallback = match image::guess_format(&bytes)? {
+            ImageFormat::Jpeg => "jpg",
+            _ => "png",
+        };
+        let stem = Path::new(path).with_extension("");
+
+        let mut srcsets: Vec<Vec<String>> = vec![Vec::new(); formats.len() + 1];
+        let mut size = (image.width(), image.height());
+        for width in scaled_widths(image.width(), widths) {
+            let resized = if width == image.width() {
+                image.clone()
+            } else {
+                image.resize_exact(width, scaled_height(&image, width), FilterType::Lanczos3)
+            };
+            size = (resized.width(), resized.height());
+
+            let all_formats = formats.iter().map(String::as_str).chain([fallback]);
+            for (format, srcset) in all_formats.zip(&mut srcsets) {
+                let name = format!("{}-{width}.{hash}.{format}", stem.display());
+                let url = self.write(&name, &resized, format)?;
+                srcset.push(format!("{url} {width}w"));
+            }
+        }
+
+        let fallback_srcset = srcsets.pop().unwrap_or_default();
+        let picture = Picture {
+            src: fallback_srcset
+                .last()
+                .and_then(|s| s.split(' ').next())
+                .unwrap_or_default()
+                .to_string(),
+            srcset: fallback_srcset.join(", "),
+            width: size.0,
+            height: size.1,
+            sources: formats
+                .iter()
+                .zip(srcsets)
+                .map(|(format, srcset)| Source {
+                    kind: format!("image/{format}"),
+                    srcset: srcset.join(", "),
+                })
+                .collect(),
+        };
+
+        self.processed.insert(key, picture.clone());
+        Ok(picture)
+    }
+
+    /// Writes `image` as `_dist/images/<name>`, encoding it only when it is
+    /// not already cached, and returns its URL.
+    fn write(&self, name: &str, image: &DynamicImage, format: &str) -> Result<String> {
+        let cached = self.cache.join(name);
+        if !cached.exists() {
+            write_atomic(&cached, &encode(image, format)?)?;
+        }
+
+        let out = self.out.join(IMAGE_DIR).join(name);
+        if !out.exists() {
+            write_atomic(&out, &std::fs::read(&cached)?)?;
+        }
+
+        Ok(format!("/{IMAGE_DIR}/{name}"))
+    }
+}
+
+/// Writes through a temporary file, as pages rendered in parallel can process
+/// the same image at once and must never copy a half written one.
+fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp = append_extension(path, format!("{}-{id}.tmp", std::process::id()));
+
+    write_file(&tmp, content)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Decodes an image with its EXIF orientation applied, since the metadata is
+/// dropped when encoding it again.
+fn decode(bytes: &[u8]) -> Result<DynamicImage> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    Ok(image)
+}
+
+fn encode(image: &DynamicImage, format: &str) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match format {
+        "avif" => image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            &mut buf, AVIF_SPEED, QUALITY,
+        ))?,
+        "webp" => {
+            let rgba = image.to_rgba8();
+            let encoder = webp::Encoder::from_rgba(&rgba, image.width(), image.height());
+            buf.extend_from_slice(&encoder.encode(f32::from(QUALITY)));
+        }
+        "jpg" => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut buf, QUALITY))?,
+        "png" => image.write_with_encoder(PngEncoder::new(&mut buf))?,
+        _ => bail!("Unsupported image format {format}, expected avif, webp, jpg or png"),
+    }
+
+    Ok(buf)
+}
+
+fn scaled_widths(original: u32, widths: &[u32]) -> Vec<u32> {
+    let mut widths = widths
+        .iter()
+        .map(|w| (*w).min(original))
+        .filter(|w| *w > 0)
+        .collect::<Vec<_>>();
+    if widths.is_empty() {
+        widths.push(original);
+    }
+
+    widths.sort_unstable();
+    widths.dedup();
+    widths
+}
+
+fn scaled_height(image: &DynamicImage, width: u32) -> u32 {
+    let height = f64::from(image.height()) * f64::from(width) / f64::from(image.width());
+    (height.round() as u32).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, ImageFormat};
+
+    use super::{Images, scaled_widths};
+    use crate::{paths::Paths, utils::TestDir};
+
+    #[test]
+    fn test_scaled_widths() {
+        assert_eq!(scaled_widths(800, &[960, 480, 480, 0]), vec![480, 800]);
+        assert_eq!(scaled_widths(800, &[]), vec![800]);
+    }
+
+    #[test]
+    fn test_process() {
+        let root = TestDir::new("images");
+        let paths = Paths::new(root.path());
+        std::fs::create_dir_all(paths.public.join("photos")).unwrap();
+        DynamicImage::new_rgb8(40, 20)
+            .save_with_format(paths.public.join("photos/cat.png"), ImageFormat::Png)
+            .unwrap();
+
+        let images = Images::new(&paths);
+        let picture = images
+            .process("photos/cat.png", &[20, 80], &["webp".to_string()])
+            .unwrap();
+
+        assert_eq!((picture.width, picture.height), (40, 20));
+        assert_eq!(picture.sources[0].kind, "image/webp");
+        assert!(picture.src.starts_with("/images/photos/cat-40."));
+        assert!(picture.src.ends_with(".png"));
+        assert_eq!(picture.srcset.split(", ").count(), 2);
+
+        let url = picture.sources[0].srcset.split(' ').next().unwrap();
+        assert!(paths.out.join(url.trim_start_matches('/')).exists());
+        assert!(paths.cache.join(url.trim_start_matches('/')).exists());
+
+        assert!(
+            images
+                .process("/photos/cat.png", &[], &[])
+                .unwrap()
+                .src
+                .starts_with("/images/photos/cat-40.")
+        );
+        for path in [
+            "../public/photos/cat.png",
+            "photos/../../x.png",
+            "./cat.png",
+        ] {
+            assert!(images.process(path, &[], &[]).is_err());
+        }
+    }
+}
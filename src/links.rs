@@ -0,0 +1,288 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Result, anyhow, bail};
+use dashmap::{DashMap, Entry};
+use minijinja::{Error, ErrorKind, Value, context, value::Kwargs};
+use url::Url;
+
+use crate::content::Content;
+
+/// Marks a link to a source file in content, like `@/pages/about.kladd`.
+const LINK_PREFIX: &str = "@/";
+
+#[derive(Debug)]
+struct Link {
+    source: PathBuf,
+    url: String,
+    frontmatter: Value,
+}
+
+/// The URL of every page by its path relative to `content/` or `templates/`,
+/// shared with the template functions so links follow slug changes.
+#[derive(Debug, Clone)]
+pub struct Links {
+    base: Url,
+    pages: Arc<DashMap<String, Link>>,
+}
+
+impl Links {
+    pub fn new(base: &Url) -> Self {
+        Links {
+            base: base.clone(),
+            pages: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Adds or updates the link to `page`, failing when a page in `content/`
+    /// and one in `templates/` have the same path.
+    pub fn insert(&self, page: &Content) -> Result<()> {
+        insert(&self.pages, page)
+    }
+
+    /// Replaces every link with those to `pages`, keeping the links as they
+    /// were when two of them have the same path.
+    pub fn replace<'a>(&self, pages: impl IntoIterator<Item = &'a Content>) -> Result<()> {
+        let links = DashMap::new();
+        for page in pages {
+            insert(&links, page)?;
+        }
+
+        self.pages.clear();
+        for (path, link) in links {
+            self.pages.insert(path, link);
+        }
+
+        Ok(())
+    }
+
+    /// The URL of the page at `path`, either absolute or relative to the
+    /// root of the site, keeping the path of the base URL.
+    pub fn url_for(&self, path: &str, absolute: bool) -> Result<String> {
+        let (url, _) = self.page(path)?;
+        if absolute {
+            Ok(url.to_string())
+        } else {
+            Ok(url.path().to_string())
+        }
+    }
+
+    /// Replaces `href` and `src` attributes like
+    /// `href="@/pages/about.kladd#team"` in rendered content with the URL of
+    /// the page, failing on pages that do not exist. Text is left alone, so
+    /// code samples keep their links as written.
+    pub fn resolve(&self, html: &str) -> Result<String> {
+        let mut resolved = String::with_capacity(html.len());
+        let mut rest = html;
+
+        while let Some(start) = rest.find('<') {
+            let len = tag_len(&rest[start..]);
+            resolved.push_str(&rest[..start]);
+            resolved.push_str(&self.resolve_tag(&rest[start..start + len])?);
+            rest = &rest[start + len..];
+        }
+
+        resolved.push_str(rest);
+        Ok(resolved)
+    }
+
+    fn resolve_tag(&self, tag: &str) -> Result<String> {
+        let mut resolved = String::with_capacity(tag.len());
+        let mut rest = tag;
+
+        while let Some(start) = rest.find(['"', '\'']) {
+            let quote = &rest[start..=start];
+            let Some(len) = rest[start + 1..].find(quote) else {
+                break;
+            };
+            let value = &rest[start + 1..start + 1 + len];
+
+            resolved.push_str(&rest[..=start]);
+            match value.strip_prefix(LINK_PREFIX) {
+                Some(link) if is_link_attribute(&rest[..start]) => {
+                    let (path, fragment) = match link.split_once('#') {
+                        Some((path, fragment)) => (path, Some(fragment)),
+                        None => (link, None),
+                    };
+                    resolved.push_str(&self.url_for(path, false)?);
+                    if let Some(fragment) = fragment {
+                        resolved.push('#');
+                        resolved.push_str(fragment);
+                    }
+                }
+                _ => resolved.push_str(value),
+            }
+            resolved.push_str(quote);
+            rest = &rest[start + len + 2..];
+        }
+
+        resolved.push_str(rest);
+        Ok(resolved)
+    }
+
+    /// The `url_for(path, absolute=false)` template function.
+    pub fn url_for_function(&self, path: &str, kwargs: Kwargs) -> Result<Value, Error> {
+        let absolute: Option<bool> = kwargs.get("absolute")?;
+        kwargs.assert_all_used()?;
+
+        self.url_for(path, absolute.unwrap_or(false))
+            .map(Value::from)
+            .map_err(|err| Error::new(ErrorKind::InvalidOperation, err.to_string()))
+    }
+
+    /// The `get_page(path)` template function, returning the frontmatter of
+    /// the page with its `url` and `canonical_url`.
+    pub fn get_page_function(&self, path: &str) -> Result<Value, Error> {
+        let (url, frontmatter) = self
+            .page(path)
+            .map_err(|err| Error::new(ErrorKind::InvalidOperation, err.to_string()))?;
+
+        Ok(context! {
+            ..frontmatter,
+            ..context! {
+                url => url.path(),
+                canonical_url => url.to_string(),
+            }
+        })
+    }
+
+    /// Every page like `get_page` returns it, sorted by path.
+    pub fn pages(&self) -> Vec<Value> {
+        let mut paths = self
+            .pages
+            .iter()
+            .map(|p| p.key().clone())
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        paths
+            .iter()
+            .filter_map(|path| self.get_page_function(path).ok())
+            .collect()
+    }
+
+    fn page(&self, path: &str) -> Result<(Url, Value)> {
+        let path = path.trim_start_matches('/');
+        let page = self
+            .pages
+            .get(path)
+            .ok_or_else(|| anyhow!("No page found for {path}"))?;
+
+        Ok((self.base.join(&page.url)?, page.frontmatter.clone()))
+    }
+}
+
+fn insert(links: &DashMap<String, Link>, page: &Content) -> Result<()> {
+    let link = Link {
+        source: page.source.clone(),
+        url: page.url.clone(),
+        frontmatter: page.frontmatter.to_context(),
+    };
+
+    match links.entry(page.relative_source.clone()) {
+        Entry::Occupied(entry) if entry.get().source != page.source => bail!(
+            "Both {} and {} are linked to as {LINK_PREFIX}{}",
+            entry.get().source.display(),
+            page.source.display(),
+            page.relative_source
+        ),
+        entry => {
+            entry.insert(link);
+        }
+    }
+
+    Ok(())
+}
+
+/// The length of the tag at the start of `html` up to and including its `>`,
+/// skipping any in quoted attribute values.
+fn tag_len(html: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in html.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+
+    html.len()
+}
+
+/// Whether the text before an attribute value, like `<a href=`, ends with an
+/// `href` or `src` attribute.
+fn is_link_attribute(before: &str) -> bool {
+    let Some(name) = before.trim_end().strip_suffix('=') else {
+        return false;
+    };
+
+    ["href", "src"].iter().any(|attr| {
+        name.trim_end()
+            .strip_suffix(attr)
+            .is_some_and(|rest| rest.ends_with(char::is_whitespace))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use dashmap::DashMap;
+    use minijinja::Value;
+    use url::Url;
+
+    use super::{Link, Links};
+
+    fn links(base: &str) -> Links {
+        let pages = [("pages/about.kladd", "about/"), ("index.html", "")]
+            .into_iter()
+            .map(|(path, url)| {
+                let link = Link {
+                    source: PathBuf::from(path),
+                    url: url.to_string(),
+                    frontmatter: Value::UNDEFINED,
+                };
+                (path.to_string(), link)
+            })
+            .collect::<DashMap<_, _>>();
+
+        Links {
+            base: Url::parse(base).unwrap(),
+            pages: Arc::new(pages),
+        }
+    }
+
+    #[test]
+    fn test_url_for() {
+        let links = links("https://example.org/blog/");
+        assert_eq!(
+            links.url_for("pages/about.kladd", false).unwrap(),
+            "/blog/about/"
+        );
+        assert_eq!(
+            links.url_for("/pages/about.kladd", true).unwrap(),
+            "https://example.org/blog/about/"
+        );
+        assert_eq!(links.url_for("index.html", false).unwrap(), "/blog/");
+        assert!(links.url_for("pages/missing.kladd", false).is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let links = links("https://example.org");
+        let html = r#"<a href="@/pages/about.kladd#team">Team</a> <a href="/other/">Other</a>"#;
+        assert_eq!(
+            links.resolve(html).unwrap(),
+            r#"<a href="/about/#team">Team</a> <a href="/other/">Other</a>"#
+        );
+
+        let html = r#"<img alt="@/index.html" src='@/index.html'> <code>&lt;a href="@/missing.kladd"&gt;</code>"#;
+        assert_eq!(
+            links.resolve(html).unwrap(),
+            r#"<img alt="@/index.html" src='/'> <code>&lt;a href="@/missing.kladd"&gt;</code>"#
+        );
+
+        let err = links.resolve(r#"<a href="@/missing.kladd">"#).unwrap_err();
+        assert_eq!(err.to_string(), "No page found for missing.kladd");
+    }
+}
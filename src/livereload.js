@@ -1,7 +1,42 @@
 (() => {
     const url = "ws://localhost:3001";
+    const overlayId = "statisk-error-overlay";
     let socket = null;
 
+    const hideError = () => {
+        document.getElementById(overlayId)?.remove();
+    };
+
+    const showError = (message) => {
+        hideError();
+
+        const overlay = document.createElement("div");
+        overlay.id = overlayId;
+        overlay.title = "Click to dismiss";
+        overlay.style.cssText = [
+            "position: fixed",
+            "inset: 0",
+            "z-index: 2147483647",
+            "overflow: auto",
+            "padding: 2rem",
+            "background: rgba(20, 20, 20, 0.95)",
+            "color: #f4f4f4",
+            "cursor: pointer",
+        ].join(";");
+        overlay.addEventListener("click", hideError);
+
+        const title = document.createElement("h2");
+        title.textContent = "Build failed";
+        title.style.cssText = "margin: 0 0 1rem; color: #ff6b6b; font: bold 1.25rem sans-serif";
+
+        const pre = document.createElement("pre");
+        pre.textContent = message;
+        pre.style.cssText = "margin: 0; white-space: pre-wrap; font: 0.9rem/1.5 monospace";
+
+        overlay.append(title, pre);
+        document.body.append(overlay);
+    };
+
     const connect = () => {
         socket = new WebSocket(url);
 
@@ -10,6 +45,12 @@
         });
 
         socket.addEventListener("message", (event) => {
+            if (event.data.startsWith("error:")) {
+                console.error("Build failed");
+                showError(event.data.slice("error:".length));
+                return;
+            }
+
             switch (event.data.trim()) {
                 case "reload":
                     console.log("Reloading page");
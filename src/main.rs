@@ -1,21 +1,38 @@
 mod asset;
 mod build_mode;
+mod citations;
 mod cli;
+mod component;
 mod compress;
 mod content;
 mod context;
+mod data;
+mod dates;
+mod diagnostics;
 mod events;
+mod filters;
 mod frontmatter;
+mod hooks;
+mod images;
+mod links;
+mod markup;
+mod math;
 mod minify;
+mod og_image;
 mod paths;
+mod plugins;
+mod related;
 mod render;
+mod scripts;
+mod search;
 mod server;
 mod statisk_config;
+mod svg;
 mod templating;
 mod utils;
 mod watcher;
 
-use std::{env::current_dir, fs, thread, time::Instant};
+use std::{env::current_dir, fs, sync::Arc, thread, time::Instant};
 
 use anyhow::{Result, bail};
 use clap::{CommandFactory, Parser};
@@ -29,7 +46,10 @@ use crate::{
     cli::{Cmds, Options, print_completion},
     context::Context,
     events::EventSender,
+    hooks::{Hooks, Stage},
+    links::Links,
     paths::Paths,
+    plugins::Plugins,
     render::Renderer,
     statisk_config::StatiskConfig,
     templating::Templates,
@@ -51,6 +71,10 @@ fn main() -> Result<()> {
 
     tracing_subscriber::registry().with(filter).with(fmt).init();
 
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(opts.jobs.unwrap_or(0))
+        .build_global()?;
+
     let root = match opts.dir {
         None => current_dir()?,
         Some(dir) => dir.canonicalize()?,
@@ -66,6 +90,10 @@ fn main() -> Result<()> {
         Ok(config) => config,
         Err(err) => bail!("could not read config: {:?}", err),
     };
+    let paths = match &config.theme {
+        Some(theme) => paths.with_theme(theme)?,
+        None => paths,
+    };
 
     match opts.cmd {
         None | Some(Cmds::Dev) => {
@@ -87,12 +115,19 @@ fn main() -> Result<()> {
     let now = Instant::now();
 
     let events = EventSender::new();
-    let templates = Templates::new(&paths.templates)?;
-    let renderer = Renderer::new(&paths.out);
-    let mut context = Context::new(templates, config, renderer, mode, events.clone());
+    let plugins = Arc::new(Plugins::load(&paths.root, &config.plugins)?);
+    let templates = Templates::new(&paths, &config, Links::new(&config.url), plugins)?;
+    let renderer = Renderer::new(&paths);
+    let hooks = Hooks::new(&paths, mode, config.hooks.clone());
+    let mut context = Context::new(templates, config, renderer, hooks, mode, events.clone());
+
+    let builds = matches!(opts.cmd, None | Some(Cmds::Dev | Cmds::Build));
+    if builds {
+        context.hooks.run(Stage::PreBuild)?;
+    }
     context.collect(&paths)?;
 
-    if matches!(opts.cmd, None | Some(Cmds::Dev | Cmds::Build)) {
+    if builds {
         if paths.out.exists() {
             tracing::debug!("Removing out directory");
             fs::remove_dir_all(&paths.out)?;
@@ -110,6 +145,8 @@ fn main() -> Result<()> {
 
     match opts.cmd {
         None | Some(Cmds::Dev) => {
+            context.hooks.run(Stage::PostBuild)?;
+
             let root = paths.out.clone();
             let watcher = thread::spawn(move || start_live_reload(&paths, &context));
 
@@ -125,6 +162,8 @@ fn main() -> Result<()> {
 
             let done = now.elapsed();
             tracing::info!("Finished compressing output in {:?}ms", done.as_millis());
+
+            context.hooks.run(Stage::PostBuild)?;
         }
         Some(Cmds::Serve) => {
             tracing::info!("serving site at http://localhost:3000/...");
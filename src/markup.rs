@@ -0,0 +1,66 @@
+use anyhow::{Result, bail};
+use kladd::{html, parser::Parser};
+use minijinja::Environment;
+
+use crate::{
+    citations::{self, Bibliography, Citations},
+    component,
+    frontmatter::Frontmatter,
+    math,
+};
+
+/// Kladd rendered to HTML, with the bibliography of the sources it cites.
+#[derive(Debug, Default)]
+pub struct Markup {
+    pub html: String,
+    pub bibliography: Option<String>,
+}
+
+/// Renders kladd `source` to HTML, rendering math and expanding components.
+pub fn to_html(source: &str, env: &Environment) -> Result<String> {
+    Ok(render(source, env, None)?.html)
+}
+
+/// Like [`to_html`], but also renders citations, which need a
+/// `bibliography` to look them up in.
+pub fn render(
+    source: &str,
+    env: &Environment,
+    bibliography: Option<&Bibliography>,
+) -> Result<Markup> {
+    let (source, math) = math::extract(source)?;
+    let (source, groups) = citations::extract(&source);
+    let citations = match (bibliography, groups.first()) {
+        (Some(bibliography), _) => Some(bibliography.cite(&groups)?),
+        (None, Some(keys)) => bail!(
+            "Cannot cite @{} without a bibliography, set one in the frontmatter or statisk.toml",
+            keys.join("; @")
+        ),
+        (None, None) => None,
+    };
+
+    let html = match component::render(&source, env)? {
+        Some(html) => html,
+        None => kladd_to_html(&source)?,
+    };
+    let html = math::substitute(html, &math);
+
+    Ok(match citations {
+        Some(Citations {
+            citations,
+            bibliography,
+        }) => Markup {
+            html: citations::substitute(html, &citations),
+            bibliography: (!bibliography.is_empty()).then_some(bibliography),
+        },
+        None => Markup {
+            html,
+            bibliography: None,
+        },
+    })
+}
+
+pub fn kladd_to_html(source: &str) -> Result<String> {
+    let (document, _): (_, Option<Frontmatter>) = Parser::new(source)?.finish();
+    Ok(html::to_html(&document)?)
+}
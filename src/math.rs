@@ -0,0 +1,133 @@
+use anyhow::{Result, anyhow};
+use katex::{Opts, OutputType};
+
+#[derive(Debug, PartialEq)]
+struct Math<'a> {
+    start: usize,
+    end: usize,
+    display: bool,
+    tex: &'a str,
+}
+
+/// Replaces inline `` $`x^2` `` and display `` $$`x^2` `` math in `source`
+/// with placeholders, returning the new source and the MathML for each of
+/// them to [`substitute`] back in after converting the source to HTML.
+///
+/// Errors point at the line and column in `source`, which is the content file
+/// unless `template = "before"` or a `before_html` plugin changed it first.
+pub fn extract(source: &str) -> Result<(String, Vec<String>)> {
+    let mut expanded = String::with_capacity(source.len());
+    let mut rendered = Vec::new();
+    let mut last = 0;
+
+    for math in find_math(source) {
+        let opts = Opts::builder()
+            .display_mode(math.display)
+            .output_type(OutputType::Mathml)
+            .build()?;
+        let mathml = katex::render_with_opts(math.tex, &opts).map_err(|err| {
+            let (line, column) = position(source, math.start);
+            anyhow!("Invalid math at line {line}, column {column}: {err}")
+        })?;
+
+        expanded.push_str(&source[last..math.start]);
+        expanded.push_str(&placeholder(rendered.len()));
+        rendered.push(mathml);
+        last = math.end;
+    }
+
+    expanded.push_str(&source[last..]);
+    Ok((expanded, rendered))
+}
+
+pub fn substitute(html: String, rendered: &[String]) -> String {
+    rendered.iter().enumerate().fold(html, |html, (i, mathml)| {
+        let placeholder = placeholder(i);
+        html.replace(&format!("<p>{placeholder}</p>"), mathml)
+            .replace(&placeholder, mathml)
+    })
+}
+
+fn placeholder(index: usize) -> String {
+    format!("STATISKMATH{index}END")
+}
+
+fn find_math(source: &str) -> Vec<Math<'_>> {
+    let mut found = Vec::new();
+    let mut i = 0;
+
+    while let Some(offset) = source[i..].find("$`") {
+        let tick = i + offset + 1;
+        let display = tick >= 2 && source.as_bytes()[tick - 2] == b'$';
+        let start = if display { tick - 2 } else { tick - 1 };
+
+        let ticks = source[tick..].bytes().take_while(|b| *b == b'`').count();
+        let fence = &source[tick..tick + ticks];
+        let content_start = tick + ticks;
+
+        let Some(len) = source[content_start..].find(fence) else {
+            break;
+        };
+        let end = content_start + len + ticks;
+
+        found.push(Math {
+            start,
+            end,
+            display,
+            tex: source[content_start..content_start + len].trim(),
+        });
+        i = end;
+    }
+
+    found
+}
+
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Math, extract, find_math, position};
+
+    #[test]
+    fn test_find_math() {
+        let source = "Costs $5, $`x^2` and\n$$`\\sum_i i` or $``a`b``";
+        assert_eq!(
+            find_math(source),
+            vec![
+                Math {
+                    start: 10,
+                    end: 16,
+                    display: false,
+                    tex: "x^2",
+                },
+                Math {
+                    start: 21,
+                    end: 33,
+                    display: true,
+                    tex: "\\sum_i i",
+                },
+                Math {
+                    start: 37,
+                    end: source.len(),
+                    display: false,
+                    tex: "a`b",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_error_position() {
+        let err = extract("+++\ntitle = \"Math\"\n+++\n\nSee $`\\frac{`").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Invalid math at line 5, column 5")
+        );
+        assert_eq!(position("ab\ncd", 4), (2, 2));
+    }
+}
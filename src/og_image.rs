@@ -0,0 +1,101 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::{Result, anyhow};
+use resvg::{
+    tiny_skia::{Pixmap, Transform},
+    usvg::{Options, Tree, fontdb::Database},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{find_files, is_file};
+
+pub const OG_IMAGE: &str = "og.png";
+/// Bundled so text renders without any fonts in `fonts/`, and used for the
+/// generic `sans-serif`, `serif` and `monospace` families.
+const DEFAULT_FONT: &[u8] = include_bytes!("fonts/DejaVuSans.ttf");
+const DEFAULT_FAMILY: &str = "DejaVu Sans";
+
+/// Renders `templates/<template>` to an `og.png` next to every public page,
+/// only using the bundled font and those in `fonts/` so images look the same
+/// on every machine. The template has to start with `_`, like `_og.svg`, so it
+/// is not built as a page.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct OgImageConfig {
+    pub template: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct OgImages {
+    fonts_dir: PathBuf,
+    fonts: OnceLock<Arc<Database>>,
+}
+
+impl OgImages {
+    pub fn new(fonts_dir: &Path) -> Self {
+        OgImages {
+            fonts_dir: fonts_dir.to_path_buf(),
+            fonts: OnceLock::new(),
+        }
+    }
+
+    /// Renders an SVG to PNG at the size of its `width` and `height`.
+    pub fn render(&self, svg: &str) -> Result<Vec<u8>> {
+        let fonts = self.fonts.get_or_init(|| {
+            let mut fonts = Database::new();
+            fonts.load_font_data(DEFAULT_FONT.to_vec());
+            fonts.set_sans_serif_family(DEFAULT_FAMILY);
+            fonts.set_serif_family(DEFAULT_FAMILY);
+            fonts.set_monospace_family(DEFAULT_FAMILY);
+            for file in find_files(&self.fonts_dir, is_file) {
+                match std::fs::read(&file) {
+                    Ok(data) => fonts.load_font_data(data),
+                    Err(err) => tracing::warn!("Could not read font {}: {err}", file.display()),
+                }
+            }
+
+            Arc::new(fonts)
+        });
+
+        let options = Options {
+            font_family: DEFAULT_FAMILY.to_string(),
+            fontdb: fonts.clone(),
+            ..Options::default()
+        };
+        let tree = Tree::from_str(svg, &options)?;
+        let size = tree.size().to_int_size();
+        let mut pixmap = Pixmap::new(size.width(), size.height())
+            .ok_or_else(|| anyhow!("Invalid preview image size"))?;
+        resvg::render(&tree, Transform::default(), &mut pixmap.as_mut());
+
+        Ok(pixmap.encode_png()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::OgImages;
+
+    #[test]
+    fn test_render() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="120" height="63"><rect width="120" height="63" fill="#123456"/></svg>"##;
+        let png = OgImages::new(Path::new("fonts")).render(svg).unwrap();
+
+        let image = image::load_from_memory(&png).unwrap();
+        assert_eq!((image.width(), image.height()), (120, 63));
+        assert_eq!(image.to_rgb8().get_pixel(10, 10).0, [0x12, 0x34, 0x56]);
+    }
+
+    #[test]
+    fn test_render_default_font() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="120" height="63"><rect width="120" height="63" fill="#000"/><text x="10" y="50" font-size="40" font-family="Missing" fill="#fff">HH</text></svg>"##;
+        let png = OgImages::new(Path::new("fonts")).render(svg).unwrap();
+
+        let image = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert!(image.pixels().any(|p| p.0 != [0, 0, 0]));
+    }
+}
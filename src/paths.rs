@@ -1,11 +1,18 @@
 use std::path::{Path, PathBuf};
 
+use anyhow::{Result, bail};
+
 const OUT_PATH: &str = "_dist";
 const TEMPLATE_PATH: &str = "templates";
 const PUBLIC_PATH: &str = "public";
 const CSS_PATH: &str = "css";
 const JS_PATH: &str = "js";
 const CONTENT_PATH: &str = "content";
+const SCRIPTS_PATH: &str = "scripts";
+const DATA_PATH: &str = "data";
+const FONTS_PATH: &str = "fonts";
+const ICONS_PATH: &str = "icons";
+const CACHE_PATH: &str = ".statisk-cache";
 
 pub const LIVERELOAD_JS: &str = include_str!("livereload.js");
 
@@ -18,6 +25,14 @@ pub struct Paths {
     pub css: PathBuf,
     pub js: PathBuf,
     pub content: PathBuf,
+    pub scripts: PathBuf,
+    pub data: PathBuf,
+    pub fonts: PathBuf,
+    pub icons: PathBuf,
+    pub cache: PathBuf,
+    /// Templates, CSS, JS, scripts, data and public files not in the site are
+    /// looked up in the theme.
+    pub theme: Option<Box<Paths>>,
 }
 
 impl Paths {
@@ -30,6 +45,29 @@ impl Paths {
             css: root.join(CSS_PATH),
             js: root.join(JS_PATH),
             content: root.join(CONTENT_PATH),
+            scripts: root.join(SCRIPTS_PATH),
+            data: root.join(DATA_PATH),
+            fonts: root.join(FONTS_PATH),
+            icons: root.join(ICONS_PATH),
+            cache: root.join(CACHE_PATH),
+            theme: None,
         }
     }
+
+    /// Adds the theme at `theme` relative to the site, which can be shared
+    /// by sites outside of it.
+    pub fn with_theme(mut self, theme: &Path) -> Result<Self> {
+        let path = self.root.join(theme);
+        let Some(theme) = path.canonicalize().ok().filter(|t| t.is_dir()) else {
+            bail!("Could not find theme {}", path.display());
+        };
+
+        self.theme = Some(Box::new(Paths::new(&theme)));
+        Ok(self)
+    }
+
+    /// The site and then its theme, in the order files are looked up in.
+    pub fn layers(&self) -> impl Iterator<Item = &Paths> {
+        std::iter::once(self).chain(self.theme.as_deref())
+    }
 }
@@ -0,0 +1,330 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use minijinja::{Environment, Error, ErrorKind, Value, value::Rest};
+use serde::{Deserialize, Serialize};
+use wasmi::{
+    Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+/// Instructions a plugin can run in a single call before it is stopped.
+const FUEL: u64 = 1_000_000_000;
+/// The most memory a plugin can use.
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// What a plugin adds, asked for when it is loaded.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Manifest {
+    filters: Vec<String>,
+    functions: Vec<String>,
+    before_html: bool,
+    after_html: bool,
+}
+
+/// The page content transforms run for.
+#[derive(Debug, Serialize)]
+pub struct Page<'a> {
+    pub path: &'a str,
+    pub frontmatter: Value,
+}
+
+/// Calls into a plugin, sent as JSON, matching `Request` in `statisk-plugin`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "call", rename_all = "snake_case")]
+enum Request<'a> {
+    Manifest,
+    Filter {
+        name: &'a str,
+        args: Vec<serde_json::Value>,
+    },
+    Function {
+        name: &'a str,
+        args: Vec<serde_json::Value>,
+    },
+    BeforeHtml {
+        page: &'a Page<'a>,
+        content: &'a str,
+    },
+    AfterHtml {
+        page: &'a Page<'a>,
+        content: &'a str,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Ok(serde_json::Value),
+    Error(String),
+}
+
+/// A loaded instance of a plugin, which only runs one call at a time.
+struct Runtime {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+    free: TypedFunc<(u32, u32), ()>,
+    call: TypedFunc<(u32, u32), u64>,
+}
+
+impl Runtime {
+    fn new(module: &Module) -> Result<Self> {
+        let limits = StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build();
+        let mut store = Store::new(module.engine(), limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(FUEL).map_err(|err| anyhow!("{err}"))?;
+        let instance = Linker::new(module.engine())
+            .instantiate(&mut store, module)?
+            .start(&mut store)?;
+
+        Ok(Runtime {
+            memory: instance
+                .get_memory(&store, "memory")
+                .context("Plugin does not export its memory")?,
+            alloc: instance.get_typed_func(&store, "statisk_alloc")?,
+            free: instance.get_typed_func(&store, "statisk_free")?,
+            call: instance.get_typed_func(&store, "statisk_call")?,
+            store,
+        })
+    }
+
+    /// Writes `request` into the memory of the plugin and reads back the
+    /// response, which the plugin allocated and is freed afterwards.
+    fn call(&mut self, request: &[u8]) -> Result<Vec<u8>> {
+        self.store.set_fuel(FUEL).map_err(|err| anyhow!("{err}"))?;
+
+        let len = u32::try_from(request.len())?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as usize, request)
+            .map_err(|err| anyhow!("{err}"))?;
+
+        let packed = self.call.call(&mut self.store, (ptr, len))?;
+        let (ptr, len) = ((packed >> 32) as u32, packed as u32);
+        let mut response = vec![0; len as usize];
+        self.memory
+            .read(&self.store, ptr as usize, &mut response)
+            .map_err(|err| anyhow!("{err}"))?;
+        self.free.call(&mut self.store, (ptr, len))?;
+
+        Ok(response)
+    }
+}
+
+/// A WebAssembly plugin without access to anything outside its own memory,
+/// limited in how long it runs and how much memory it uses.
+pub struct Plugin {
+    name: String,
+    manifest: Manifest,
+    module: Module,
+    runtime: Mutex<Runtime>,
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Self> {
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |n| n.to_string_lossy().to_string(),
+        );
+        let wasm = std::fs::read(path)?;
+
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &wasm[..])?;
+        if let Some(import) = module.imports().next() {
+            bail!(
+                "Plugins cannot import anything, but it imports {}::{}",
+                import.module(),
+                import.name()
+            );
+        }
+
+        let runtime = Runtime::new(&module)?;
+        let mut plugin = Plugin {
+            name,
+            manifest: Manifest::default(),
+            module,
+            runtime: Mutex::new(runtime),
+        };
+        plugin.manifest = serde_json::from_value(plugin.call(&Request::Manifest)?)?;
+
+        Ok(plugin)
+    }
+
+    fn call(&self, request: &Request) -> Result<serde_json::Value> {
+        let request = serde_json::to_vec(request)?;
+        let mut runtime = self
+            .runtime
+            .lock()
+            .map_err(|_| anyhow!("Plugin {} crashed earlier", self.name))?;
+        let response = match runtime.call(&request) {
+            Ok(response) => response,
+            // A trap can leave the memory of the plugin half updated, so the
+            // next call gets a fresh instance
+            Err(err) => {
+                *runtime = Runtime::new(&self.module)?;
+                return Err(err).with_context(|| format!("Plugin {} failed", self.name));
+            }
+        };
+        drop(runtime);
+
+        match serde_json::from_slice(&response)
+            .with_context(|| format!("Invalid response from plugin {}", self.name))?
+        {
+            Response::Ok(value) => Ok(value),
+            Response::Error(err) => bail!("{err}"),
+        }
+    }
+
+    fn transform(&self, request: &Request) -> Result<String> {
+        match self.call(request)? {
+            serde_json::Value::String(content) => Ok(content),
+            _ => bail!("Plugin {} did not return a string", self.name),
+        }
+    }
+}
+
+/// Every plugin in `plugins` in `statisk.toml`, in order.
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+}
+
+impl Plugins {
+    /// Loads plugins from paths relative to the site root.
+    pub fn load(root: &Path, paths: &[PathBuf]) -> Result<Self> {
+        let plugins = paths
+            .iter()
+            .map(|path| {
+                let path = root.join(path);
+                Plugin::load(&path)
+                    .with_context(|| format!("Could not load plugin {}", path.display()))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Plugins { plugins })
+    }
+
+    /// Adds the filters and functions of every plugin to `env`.
+    pub fn register(self: &Arc<Self>, env: &mut Environment) {
+        for (index, plugin) in self.plugins.iter().enumerate() {
+            for name in &plugin.manifest.filters {
+                let (plugins, filter) = (self.clone(), name.clone());
+                env.add_filter(name.clone(), move |value: Value, args: Rest<Value>| {
+                    let args = [value].into_iter().chain(args.0).collect();
+                    plugins.invoke(index, &filter, args, false)
+                });
+            }
+            for name in &plugin.manifest.functions {
+                let (plugins, function) = (self.clone(), name.clone());
+                env.add_function(name.clone(), move |args: Rest<Value>| {
+                    plugins.invoke(index, &function, args.0, true)
+                });
+            }
+        }
+    }
+
+    fn invoke(
+        &self,
+        index: usize,
+        name: &str,
+        args: Vec<Value>,
+        is_function: bool,
+    ) -> Result<Value, Error> {
+        let args = args
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| Error::new(ErrorKind::InvalidOperation, err.to_string()))?;
+        let request = if is_function {
+            Request::Function { name, args }
+        } else {
+            Request::Filter { name, args }
+        };
+
+        self.plugins[index]
+            .call(&request)
+            .map(|value| Value::from_serialize(&value))
+            .map_err(|err| Error::new(ErrorKind::InvalidOperation, format!("{err:#}")))
+    }
+
+    /// Runs the source of `page` through every plugin before it is converted
+    /// to HTML.
+    pub fn before_html(&self, page: &Page, content: &str) -> Result<String> {
+        let mut content = content.to_string();
+        for plugin in self.plugins.iter().filter(|p| p.manifest.before_html) {
+            content = plugin.transform(&Request::BeforeHtml {
+                page,
+                content: &content,
+            })?;
+        }
+
+        Ok(content)
+    }
+
+    /// Runs the HTML of `page` through every plugin after it is converted.
+    pub fn after_html(&self, page: &Page, content: &str) -> Result<String> {
+        let mut content = content.to_string();
+        for plugin in self.plugins.iter().filter(|p| p.manifest.after_html) {
+            content = plugin.transform(&Request::AfterHtml {
+                page,
+                content: &content,
+            })?;
+        }
+
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, process::Command, sync::Arc};
+
+    use minijinja::{Environment, Value};
+
+    use super::{Page, Plugins};
+
+    #[test]
+    #[ignore = "needs the wasm32-unknown-unknown target, run with --ignored"]
+    fn test_example_plugin() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/plugins/example");
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--release", "--target", "wasm32-unknown-unknown"])
+            .args(["--target-dir", "target"])
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert!(
+            status.success(),
+            "Could not build the example plugin, is the wasm32-unknown-unknown target installed?"
+        );
+
+        let wasm = dir.join("target/wasm32-unknown-unknown/release/statisk_example_plugin.wasm");
+        let plugins = Arc::new(Plugins::load(&dir, &[wasm]).unwrap());
+        let mut env = Environment::new();
+        plugins.register(&mut env);
+        assert_eq!(
+            env.render_str("{{ 'hello'|shout }} {{ answer() }}", ())
+                .unwrap(),
+            "HELLO 42"
+        );
+        assert!(env.render_str("{{ 1|shout }}", ()).is_err());
+
+        let page = Page {
+            path: "posts/hello.kladd",
+            frontmatter: Value::UNDEFINED,
+        };
+        assert_eq!(plugins.before_html(&page, "Hi :wave:").unwrap(), "Hi 👋");
+        assert_eq!(
+            plugins
+                .after_html(&page, r#"<a href="https://example.org">"#)
+                .unwrap(),
+            r#"<a rel="external" href="https://example.org">"#
+        );
+    }
+}
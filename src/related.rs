@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ahash::AHashMap;
+use anyhow::Result;
+use minijinja::Environment;
+
+use crate::{content::ContentType, context::Context, utils::strip_html};
+
+/// How much a single shared taxonomy term counts compared to the textual
+/// similarity, which is always in the range `0.0..=1.0`.
+const TAXONOMY_WEIGHT: f64 = 1.0;
+
+#[derive(Debug)]
+pub struct Document {
+    key: String,
+    terms: BTreeSet<(String, String)>,
+    words: BTreeMap<String, f64>,
+}
+
+impl Document {
+    pub fn new(key: String, text: &str, taxonomies: &BTreeMap<String, Vec<String>>) -> Self {
+        let terms = taxonomies
+            .iter()
+            .flat_map(|(taxonomy, terms)| {
+                terms
+                    .iter()
+                    .map(|term| (taxonomy.to_lowercase(), term.to_lowercase()))
+            })
+            .collect();
+
+        let mut words = BTreeMap::new();
+        for word in tokenize(text) {
+            *words.entry(word).or_insert(0.0) += 1.0;
+        }
+
+        Document { key, terms, words }
+    }
+}
+
+pub fn collect(context: &Context, env: &Environment) -> Result<Vec<Document>> {
+    let mut documents = context
+        .pages
+        .iter()
+        .filter(|p| matches!(p.kind, ContentType::Kladd) && p.is_public_page())
+        .map(|p| {
+            let html = context.page_context(env, &p)?.get_attr("content")?;
+            let text = strip_html(html.as_str().unwrap_or_default());
+            Ok(Document::new(
+                p.key().clone(),
+                &text,
+                &p.frontmatter.taxonomies,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    documents.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(documents)
+}
+
+/// Ranks every document against all others by shared taxonomy terms and the
+/// cosine similarity of their TF-IDF vectors, returning at most `limit`
+/// related keys per document. Ties are broken by key so the result is stable
+/// between builds.
+pub fn rank(documents: &[Document], limit: usize) -> AHashMap<String, Vec<String>> {
+    let vectors = tf_idf(documents);
+
+    documents
+        .iter()
+        .zip(&vectors)
+        .map(|(document, vector)| {
+            let mut scores = documents
+                .iter()
+                .zip(&vectors)
+                .filter(|(other, _)| other.key != document.key)
+                .map(|(other, other_vector)| {
+                    let shared = document.terms.intersection(&other.terms).count() as f64;
+                    let score = shared * TAXONOMY_WEIGHT + cosine(vector, other_vector);
+                    (score, &other.key)
+                })
+                .filter(|(score, _)| *score > 0.0)
+                .collect::<Vec<_>>();
+
+            scores.sort_by(|(a_score, a_key), (b_score, b_key)| {
+                b_score.total_cmp(a_score).then_with(|| a_key.cmp(b_key))
+            });
+
+            let related = scores
+                .into_iter()
+                .take(limit)
+                .map(|(_, key)| key.clone())
+                .collect();
+
+            (document.key.clone(), related)
+        })
+        .collect()
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() > 2)
+        .map(str::to_lowercase)
+}
+
+fn tf_idf(documents: &[Document]) -> Vec<BTreeMap<&str, f64>> {
+    let mut frequency: BTreeMap<&str, f64> = BTreeMap::new();
+    for document in documents {
+        for word in document.words.keys() {
+            *frequency.entry(word).or_insert(0.0) += 1.0;
+        }
+    }
+
+    let total = documents.len() as f64;
+    documents
+        .iter()
+        .map(|document| {
+            let count: f64 = document.words.values().sum();
+            let mut vector: BTreeMap<&str, f64> = document
+                .words
+                .iter()
+                .map(|(word, n)| {
+                    let idf = ((1.0 + total) / (1.0 + frequency[word.as_str()])).ln() + 1.0;
+                    (word.as_str(), n / count * idf)
+                })
+                .collect();
+
+            let norm = vector.values().map(|v| v * v).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                vector.values_mut().for_each(|v| *v /= norm);
+            }
+
+            vector
+        })
+        .collect()
+}
+
+fn cosine(a: &BTreeMap<&str, f64>, b: &BTreeMap<&str, f64>) -> f64 {
+    a.iter()
+        .filter_map(|(word, x)| b.get(word).map(|y| x * y))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{Document, rank};
+
+    fn document(key: &str, text: &str, tags: &[&str]) -> Document {
+        let mut taxonomies = BTreeMap::new();
+        taxonomies.insert(
+            "tags".to_string(),
+            tags.iter().map(ToString::to_string).collect(),
+        );
+        Document::new(key.to_string(), text, &taxonomies)
+    }
+
+    #[test]
+    fn test_rank_related() {
+        let documents = vec![
+            document("rust.kladd", "borrow checker lifetimes traits", &["rust"]),
+            document("traits.kladd", "traits and generics", &["rust"]),
+            document("bread.kladd", "sourdough starter flour", &["baking"]),
+            document("cake.kladd", "flour sugar eggs", &[]),
+        ];
+
+        let related = rank(&documents, 5);
+        assert_eq!(related["rust.kladd"], vec!["traits.kladd"]);
+        assert_eq!(related["bread.kladd"], vec!["cake.kladd"]);
+    }
+
+    #[test]
+    fn test_rank_limit_and_ties() {
+        let documents = vec![
+            document("a.kladd", "same words here", &[]),
+            document("c.kladd", "same words here", &[]),
+            document("b.kladd", "same words here", &[]),
+        ];
+
+        let related = rank(&documents, 1);
+        assert_eq!(related["a.kladd"], vec!["b.kladd"]);
+        assert_eq!(related["c.kladd"], vec!["a.kladd"]);
+    }
+}
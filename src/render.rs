@@ -1,23 +1,30 @@
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use anyhow::Result;
+use minijinja::Environment;
+use rayon::prelude::*;
 
 use crate::{
     asset::PublicFile,
-    content::ContentType,
+    content::{Content, ContentType},
     context::Context,
     minify::{self},
-    utils::{copy_file, write_file},
+    og_image::OgImages,
+    paths::Paths,
+    search::{SearchDocument, SearchIndex},
+    utils::{copy_file, extension, write_file},
 };
 
 pub struct Renderer {
     pub dest: PathBuf,
+    og_images: OgImages,
 }
 
 impl Renderer {
-    pub fn new(dest: &Path) -> Self {
+    pub fn new(paths: &Paths) -> Self {
         Renderer {
-            dest: dest.to_path_buf(),
+            dest: paths.out.clone(),
+            og_images: OgImages::new(&paths.fonts),
         }
     }
 
@@ -31,42 +38,87 @@ impl Renderer {
         Ok(())
     }
 
+    /// Renders every page on the thread pool. Pages are rendered in order of
+    /// their source so the search index is the same with any number of jobs.
     pub fn write_content(&self, context: &Context) -> Result<()> {
-        for page in context.pages.iter() {
-            let f = page.value();
+        let env = context.templates.environment.acquire_env()?;
+        let env: &Environment = &env;
+        let mut pages = context.pages.iter().collect::<Vec<_>>();
+        pages.sort_by(|a, b| a.key().cmp(b.key()));
+
+        // Every page lists all others in `pages`, so their contexts come first
+        context.prepare_contexts(env)?;
+
+        let documents = pages
+            .par_iter()
+            .map(|page| self.write_page(env, page.value(), context))
+            .collect::<Result<Vec<_>>>()?;
+
+        if context.config.search.enabled {
+            SearchIndex::new(documents.into_iter().flatten().collect()).write(&self.dest)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every output of a page and its preview image, returning the
+    /// documents to add to the search index.
+    fn write_page(
+        &self,
+        env: &Environment,
+        f: &Content,
+        context: &Context,
+    ) -> Result<Vec<SearchDocument>> {
+        let mut documents = Vec::new();
+        for out_path in f.outputs(&context.config) {
+            let is_html = extension(&out_path) == "html";
+            let content = f
+                .render_output(env, &out_path, context.mode, context)
+                .map_err(|err| context.templates.diagnose(err, &f.source))?;
+            if context.config.search.enabled && is_html && f.is_searchable() {
+                documents.push(SearchDocument::from_html(f.search_page(), &content));
+            }
+
             write_file(
-                &self.dest.join(&f.out_path),
+                &self.dest.join(&out_path),
                 match (context.mode.optimize(), f.kind) {
-                    (true, ContentType::XML | ContentType::Unknown) => {
-                        f.render(context.mode, context)?.into()
-                    }
-                    (true, ContentType::HTML | ContentType::Kladd) => {
-                        minify::html(&f.render(context.mode, context)?)?
+                    (true, ContentType::HTML | ContentType::Kladd) if is_html => {
+                        minify::html(&content)?
                     }
-                    (false, _) => f.render(context.mode, context)?.into(),
+                    _ => content.into(),
                 },
             )?;
         }
 
-        Ok(())
+        if let Some(template) = &context.config.og_image.template
+            && f.is_public_page()
+        {
+            let svg = f
+                .og_image_svg(env, template, context)
+                .map_err(|err| context.templates.diagnose(err, &f.source))?;
+            write_file(
+                &self.dest.join(f.og_image_path()),
+                self.og_images.render(&svg)?,
+            )?;
+        }
+
+        Ok(documents)
     }
 
     pub fn copy_public_files(&self, files: &[PublicFile]) -> Result<()> {
         files
-            .iter()
+            .par_iter()
             .try_for_each(|f| copy_file(&self.dest, &f.prefix, &f.path))
     }
 
     pub fn write_assets(&self, context: &Context) -> Result<()> {
-        for asset in context.assets.iter() {
-            let asset = asset.value();
+        let assets = context.assets.iter().collect::<Vec<_>>();
+        assets.par_iter().try_for_each(|asset| {
             write_file(
                 &self.dest.join(asset.build_path.file_name().unwrap()),
                 &asset.content,
-            )?;
-        }
-
-        Ok(())
+            )
+        })
     }
 
     fn create_dest(&self) -> Result<()> {
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use ahash::AHashSet;
+use anyhow::{Context, Result};
+use minijinja::{Environment, Error, ErrorKind, Value, value::Rest};
+use rhai::{
+    AST, CallFnOptions, Dynamic, Engine, EvalAltResult, FnAccess, Scope,
+    module_resolvers::DummyModuleResolver, serde::to_dynamic,
+};
+use walkdir::DirEntry;
+
+use crate::{context::find_layered_files, links::Links, paths::Paths};
+
+/// Operations a single call of a script function can run before it is stopped.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+fn is_script(entry: &DirEntry) -> bool {
+    entry.path().extension().is_some_and(|e| e == "rhai")
+}
+
+/// The public functions in `.rhai` files in `scripts/`, added as both
+/// template functions and filters, with `config()`, `pages()` and `data()`
+/// to read the site from.
+pub struct Scripts {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Scripts {
+    pub fn load(paths: &Paths, config: Dynamic, data: &Value, links: Links) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.on_print(|text| tracing::info!("{text}"));
+        engine.on_debug(|text, _, pos| tracing::debug!("{pos}: {text}"));
+
+        let data = to_dynamic(data)?;
+        engine.register_fn("config", move || config.clone());
+        engine.register_fn("data", move || data.clone());
+        engine.register_fn("pages", move || -> Result<Dynamic, Box<EvalAltResult>> {
+            to_dynamic(links.pages())
+        });
+
+        // Functions in the site replace those with the same name in the theme
+        let mut ast = AST::empty();
+        for (_, path) in find_layered_files(paths, |p| &p.scripts, is_script)
+            .into_iter()
+            .rev()
+        {
+            let script = engine
+                .compile_file(path.clone())
+                .with_context(|| format!("Could not compile {}", path.display()))?;
+            ast.combine(script);
+        }
+
+        Ok(Scripts { engine, ast })
+    }
+
+    /// Adds every public script function to `env` by its name.
+    pub fn register(self: &Arc<Self>, env: &mut Environment) {
+        let names = self
+            .ast
+            .iter_functions()
+            .filter(|f| f.access == FnAccess::Public)
+            .map(|f| f.name.to_string())
+            .collect::<AHashSet<_>>();
+
+        for name in names {
+            let (scripts, function) = (self.clone(), name.clone());
+            env.add_function(name.clone(), move |args: Rest<Value>| {
+                scripts.call(&function, args.0)
+            });
+            let (scripts, filter) = (self.clone(), name.clone());
+            env.add_filter(name, move |value: Value, args: Rest<Value>| {
+                let args = [value].into_iter().chain(args.0).collect();
+                scripts.call(&filter, args)
+            });
+        }
+    }
+
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        let args = args
+            .iter()
+            .map(to_dynamic)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| Error::new(ErrorKind::InvalidOperation, err.to_string()))?;
+
+        // Scripts only define functions, so statements outside them are not run
+        let options = CallFnOptions::new().eval_ast(false);
+        self.engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, name, args)
+            .map(|value| Value::from_serialize(&value))
+            .map_err(|err| Error::new(ErrorKind::InvalidOperation, format!("{name}: {err}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use minijinja::{Environment, context};
+    use rhai::serde::to_dynamic;
+    use url::Url;
+
+    use super::Scripts;
+    use crate::{links::Links, paths::Paths, utils::TestDir};
+
+    #[test]
+    fn test_scripts() {
+        let root = TestDir::new("scripts");
+        let paths = Paths::new(root.path());
+        std::fs::create_dir_all(&paths.scripts).unwrap();
+        std::fs::write(
+            paths.scripts.join("helpers.rhai"),
+            r#"
+            fn shout(text) { text.to_upper() + "!" }
+            fn greet(name) { `${config().title} greets ${name}` }
+            fn author_count() { data().authors.len() }
+            fn spin() { loop {} }
+            private fn helper() { 1 }
+            "#,
+        )
+        .unwrap();
+
+        let config = to_dynamic(context! { title => "Statisk" }).unwrap();
+        let data = context! { authors => vec!["a", "b"] };
+        let links = Links::new(&Url::parse("https://example.org").unwrap());
+        let scripts = Arc::new(Scripts::load(&paths, config, &data, links).unwrap());
+        let mut env = Environment::new();
+        scripts.register(&mut env);
+
+        let render = |source| env.render_str(source, ());
+        assert_eq!(render("{{ 'hi'|shout }}").unwrap(), "HI!");
+        assert_eq!(render("{{ shout('hi') }}").unwrap(), "HI!");
+        assert_eq!(render("{{ greet('you') }}").unwrap(), "Statisk greets you");
+        assert_eq!(render("{{ author_count() }}").unwrap(), "2");
+        assert!(render("{{ spin() }}").is_err());
+        assert!(render("{{ helper() }}").is_err());
+    }
+}
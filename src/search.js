@@ -0,0 +1,83 @@
+(() => {
+    const script = document.currentScript;
+    const base = script?.dataset.index ?? "/search/";
+    const cache = new Map();
+
+    const fetchJson = (name) => {
+        if (!cache.has(name)) {
+            cache.set(
+                name,
+                fetch(`${base}${name}.json`).then((res) => (res.ok ? res.json() : {})),
+            );
+        }
+        return cache.get(name);
+    };
+
+    const terms = (query) =>
+        query
+            .toLowerCase()
+            .split(/[^\p{L}\p{N}]+/u)
+            .filter((term) => term.length > 0);
+
+    const shard = (term) => term.codePointAt(0).toString(16);
+
+    const search = async (query) => {
+        const words = terms(query);
+        if (words.length === 0) return [];
+
+        const pages = await fetchJson("pages");
+        const scores = new Map();
+
+        for (const [i, word] of words.entries()) {
+            const index = await fetchJson(shard(word));
+            const matches = new Map();
+
+            for (const [term, postings] of Object.entries(index)) {
+                // Allow prefix matches on the last word while typing
+                const last = i === words.length - 1;
+                if (term !== word && !(last && term.startsWith(word))) continue;
+
+                for (const [id, weight] of postings) {
+                    matches.set(id, (matches.get(id) ?? 0) + (term === word ? weight : weight / 2));
+                }
+            }
+
+            for (const [id, weight] of matches) {
+                const previous = i === 0 ? 0 : scores.get(id);
+                if (previous !== undefined) scores.set(id, previous + weight);
+            }
+            for (const id of scores.keys()) {
+                if (!matches.has(id)) scores.delete(id);
+            }
+        }
+
+        return [...scores.entries()]
+            .sort(([a, x], [b, y]) => y - x || a - b)
+            .map(([id, score]) => ({ ...pages[id], score }));
+    };
+
+    window.statiskSearch = search;
+
+    const input = document.querySelector("[data-statisk-search]");
+    const results = document.querySelector("[data-statisk-results]");
+    if (!input || !results) return;
+
+    input.addEventListener("input", async () => {
+        const found = await search(input.value);
+        results.replaceChildren(
+            ...found.slice(0, 20).map((page) => {
+                const item = document.createElement("li");
+                const link = document.createElement("a");
+                link.href = page.url;
+                link.textContent = page.title;
+                item.append(link);
+                if (page.description) {
+                    const description = document.createElement("p");
+                    description.textContent = page.description;
+                    item.append(description);
+                }
+                return item;
+            }),
+        );
+    });
+})();
@@ -0,0 +1,184 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::utils::{strip_html, write_file};
+
+pub const SEARCH_JS: &str = include_str!("search.js");
+
+pub const INDEX_DIR: &str = "search";
+const TITLE_WEIGHT: u32 = 10;
+const HEADING_WEIGHT: u32 = 5;
+const DESCRIPTION_WEIGHT: u32 = 3;
+const BODY_WEIGHT: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct SearchPage {
+    pub url: String,
+    pub title: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SearchDocument {
+    pub page: SearchPage,
+    pub headings: Vec<String>,
+    pub body: String,
+}
+
+impl SearchDocument {
+    /// Extracts the headings and text from the `<main>` element of a rendered
+    /// page, or the `<body>` if there is none.
+    pub fn from_html(page: SearchPage, html: &str) -> Self {
+        let main = element(html, "main")
+            .or_else(|| element(html, "body"))
+            .unwrap_or(html);
+
+        SearchDocument {
+            page,
+            headings: headings(main),
+            body: strip_html(main),
+        }
+    }
+}
+
+/// A search index with a list of pages and the terms in them, sharded by the
+/// first character of each term so clients only fetch what they search for.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    pub pages: Vec<SearchPage>,
+    pub shards: BTreeMap<String, BTreeMap<String, Vec<(usize, u32)>>>,
+}
+
+impl SearchIndex {
+    pub fn new(documents: Vec<SearchDocument>) -> Self {
+        let mut index = SearchIndex::default();
+        let mut documents = documents;
+        documents.sort_by(|a, b| a.page.url.cmp(&b.page.url));
+
+        for (id, document) in documents.into_iter().enumerate() {
+            let mut weights: BTreeMap<String, u32> = BTreeMap::new();
+            let mut add = |text: &str, weight| {
+                for term in terms(text) {
+                    *weights.entry(term).or_insert(0) += weight;
+                }
+            };
+
+            add(&document.page.title, TITLE_WEIGHT);
+            document
+                .headings
+                .iter()
+                .for_each(|h| add(h, HEADING_WEIGHT));
+            if let Some(description) = &document.page.description {
+                add(description, DESCRIPTION_WEIGHT);
+            }
+            add(&document.body, BODY_WEIGHT);
+
+            for (term, weight) in weights {
+                index
+                    .shards
+                    .entry(shard(&term))
+                    .or_default()
+                    .entry(term)
+                    .or_default()
+                    .push((id, weight));
+            }
+            index.pages.push(document.page);
+        }
+
+        index
+    }
+
+    pub fn write(&self, dest: &Path) -> Result<()> {
+        let dir = dest.join(INDEX_DIR);
+        write_file(&dir.join("pages.json"), serde_json::to_vec(&self.pages)?)?;
+        for (shard, terms) in &self.shards {
+            write_file(
+                &dir.join(format!("{shard}.json")),
+                serde_json::to_vec(terms)?,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Shards are named by the hex code point of the first character, which keeps
+/// file names safe for any language and is easy to compute in the browser.
+fn shard(term: &str) -> String {
+    term.chars()
+        .next()
+        .map(|c| format!("{:x}", c as u32))
+        .unwrap_or_default()
+}
+
+fn terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn element<'a>(html: &'a str, tag: &str) -> Option<&'a str> {
+    let start = html.find(&format!("<{tag}"))?;
+    let end = html[start..].find(&format!("</{tag}>"))?;
+    Some(&html[start..start + end])
+}
+
+fn headings(html: &str) -> Vec<String> {
+    let mut headings = Vec::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find("<h") {
+        rest = &rest[start + 2..];
+        let Some(level) = rest.chars().next().filter(|c| ('1'..='6').contains(c)) else {
+            continue;
+        };
+
+        let Some(end) = rest.find(&format!("</h{level}>")) else {
+            break;
+        };
+        let heading = strip_html(&format!("<h{}", &rest[..end]));
+        headings.push(heading.split_whitespace().collect::<Vec<_>>().join(" "));
+        rest = &rest[end..];
+    }
+
+    headings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SearchDocument, SearchIndex, SearchPage};
+
+    fn document(url: &str, title: &str, html: &str) -> SearchDocument {
+        let page = SearchPage {
+            url: url.to_string(),
+            title: title.to_string(),
+            description: None,
+        };
+        SearchDocument::from_html(page, html)
+    }
+
+    #[test]
+    fn test_extract_main() {
+        let html = r#"<body><nav>Home</nav><main><h1 id="a">Hello <em>there</em></h1><p>World</p><h2>Next</h2></main></body>"#;
+        let document = document("/", "Home", html);
+
+        assert_eq!(document.headings, vec!["Hello there", "Next"]);
+        assert!(!document.body.contains("Home"));
+        assert!(document.body.contains("World"));
+    }
+
+    #[test]
+    fn test_index_weights_and_shards() {
+        let index = SearchIndex::new(vec![
+            document("/b/", "Rust", "<main><p>Rust and more rust</p></main>"),
+            document("/a/", "Baking", "<main><p>Rust on the oven</p></main>"),
+        ]);
+
+        assert_eq!(index.pages[0].url, "/a/");
+        let shard = &index.shards["72"];
+        assert_eq!(shard["rust"], vec![(0, 1), (1, 12)]);
+        assert!(index.shards["62"].contains_key("baking"));
+    }
+}
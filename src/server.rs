@@ -46,6 +46,8 @@ fn create_websocket_server(events: &EventSender) -> Result<()> {
 struct NotificationServer {
     rx: Receiver<Event>,
     clients: Arc<RwLock<Vec<WebSocket<TcpStream>>>>,
+    /// The error from the last rebuild, sent to clients connecting after it.
+    error: Arc<RwLock<Option<String>>>,
 }
 
 impl NotificationServer {
@@ -53,6 +55,7 @@ impl NotificationServer {
         NotificationServer {
             rx,
             clients: Arc::new(RwLock::new(Vec::new())),
+            error: Arc::new(RwLock::new(None)),
         }
     }
 
@@ -61,16 +64,24 @@ impl NotificationServer {
         let server = TcpListener::bind(addr)?;
 
         let connections_events = self.clients.clone();
+        let last_error = self.error.clone();
 
         spawn(move || {
             while let Ok(event) = self.rx.recv() {
+                let (message, error) = match event {
+                    Event::Reload => ("reload".to_string(), None),
+                    Event::Error(error) => (format!("error:{error}"), Some(error)),
+                };
+                if let Ok(mut last_error) = last_error.write() {
+                    *last_error = error;
+                }
+
                 if let Ok(mut connections) = connections_events.write() {
                     connections.retain_mut(|websocket| {
-                        let message = match event {
-                            Event::Reload => Message::Text("reload".into()),
-                        };
-
-                        if websocket.send(message).is_ok() {
+                        if websocket
+                            .send(Message::Text(message.as_str().into()))
+                            .is_ok()
+                        {
                             true
                         } else {
                             let _ = websocket.close(None);
@@ -85,11 +96,17 @@ impl NotificationServer {
             stream.set_nodelay(true)?;
 
             let connections = self.clients.clone();
+            let error = self.error.clone();
 
             spawn(move || {
-                if let Ok(websocket) = accept(stream)
+                if let Ok(mut websocket) = accept(stream)
                     && let Ok(mut connections) = connections.write()
                 {
+                    if let Ok(error) = error.read()
+                        && let Some(error) = error.as_ref()
+                    {
+                        let _ = websocket.send(Message::Text(format!("error:{error}").into()));
+                    }
                     connections.push(websocket);
                 }
             });
@@ -181,6 +198,11 @@ impl FileServe {
         let mime = new_mime_guess::from_ext(&extension(&path))
             .first_or_text_plain()
             .to_string();
+        let mime = if mime.starts_with("text/") || mime == "application/json" {
+            format!("{mime}; charset=utf-8")
+        } else {
+            mime
+        };
         ResponseBuilder::new()
             .status(StatusCode::OK)
             .header(CONTENT_TYPE, mime)
@@ -1,22 +1,80 @@
-use std::{fs::read_to_string, path::Path};
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
 
 use ahash::AHashMap;
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
+use toml::Table;
 use url::Url;
 
-use crate::build_mode::BuildMode;
+use crate::{
+    build_mode::BuildMode, citations::BibliographyConfig, dates::DateSource, hooks::HooksConfig,
+    og_image::OgImageConfig, svg::IconsConfig, templating::is_partial,
+};
+
+/// The config file in a theme, with defaults for every site using it.
+pub const THEME_CONFIG: &str = "theme.toml";
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct StatiskConfig {
     pub url: Url,
+    /// A directory with templates, CSS, JS and public files used when the
+    /// site has none of its own, relative to the site.
+    pub theme: Option<PathBuf>,
     pub title: Option<String>,
     pub description: Option<String>,
     pub author: Option<Author>,
     #[serde(default)]
+    pub dates: DateSource,
+    #[serde(default)]
+    pub related: RelatedConfig,
+    #[serde(default)]
+    pub sections: AHashMap<String, SectionConfig>,
+    #[serde(default)]
+    pub search: SearchConfig,
+    #[serde(default)]
+    pub bibliography: BibliographyConfig,
+    #[serde(default)]
+    pub og_image: OgImageConfig,
+    #[serde(default)]
+    pub icons: IconsConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// WebAssembly plugins, relative to the site, run in order.
+    #[serde(default)]
+    pub plugins: Vec<PathBuf>,
+    #[serde(default)]
     pub extra: AHashMap<String, String>,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RelatedConfig {
+    pub limit: usize,
+}
+
+impl Default for RelatedConfig {
+    fn default() -> Self {
+        RelatedConfig { limit: 5 }
+    }
+}
+
+/// Builds a search index of all public pages into `search/` and adds the
+/// `search.js` asset to query it.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SearchConfig {
+    pub enabled: bool,
+}
+
+/// Settings for all content in a directory under `content/`, including its
+/// subdirectories unless they have their own.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SectionConfig {
+    pub outputs: Option<Vec<String>>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Author {
     pub name: String,
@@ -27,11 +85,109 @@ pub struct Author {
 impl StatiskConfig {
     pub fn from_path(path: &Path, mode: BuildMode) -> Result<StatiskConfig> {
         let content = read_to_string(path)?;
-        let mut config: StatiskConfig = toml::from_str(&content)?;
+        let mut table: Table = toml::from_str(&content)?;
+        if let Some(theme) = table.get("theme").and_then(|t| t.as_str())
+            && let Some(root) = path.parent()
+        {
+            let theme_config = root.join(theme).join(THEME_CONFIG);
+            if theme_config.is_file() {
+                let content = read_to_string(&theme_config)?;
+                let mut defaults: Table = toml::from_str(&content)
+                    .with_context(|| format!("Invalid theme config {}", theme_config.display()))?;
+                merge(&mut defaults, table);
+                table = defaults;
+            }
+        }
+
+        let mut config: StatiskConfig = table.try_into()?;
+        if let Some(file) = &mut config.bibliography.file
+            && let Some(root) = path.parent()
+        {
+            *file = root.join(&file);
+        }
+        if let Some(template) = &config.og_image.template
+            && !is_partial(Path::new(template))
+        {
+            bail!("The preview image template {template} must start with `_`, like `_og.svg`");
+        }
         if mode.normal() {
             config.url = Url::parse("http://localhost:3000")?;
         }
 
         Ok(config)
     }
+
+    pub fn section_outputs(&self, dir: Option<&str>) -> Option<&Vec<String>> {
+        let mut dir = Path::new(dir?);
+        loop {
+            let outputs = self
+                .sections
+                .get(dir.to_string_lossy().as_ref())
+                .and_then(|s| s.outputs.as_ref());
+            if outputs.is_some() {
+                return outputs;
+            }
+
+            dir = dir.parent().filter(|p| p.components().count() > 0)?;
+        }
+    }
+}
+
+/// Merges `overrides` into `base`, replacing everything but tables, which
+/// are merged key by key.
+fn merge(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(value)) => merge(base, value),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml::Table;
+
+    use super::merge;
+
+    #[test]
+    fn test_merge() {
+        let mut theme: Table = toml::from_str(
+            r#"
+            title = "Company"
+            url = "https://example.org"
+            [search]
+            enabled = true
+            [extra]
+            color = "blue"
+            logo = "logo.svg"
+            "#,
+        )
+        .unwrap();
+        let site: Table = toml::from_str(
+            r#"
+            title = "Blog"
+            [extra]
+            color = "red"
+            "#,
+        )
+        .unwrap();
+
+        merge(&mut theme, site);
+        let expected: Table = toml::from_str(
+            r#"
+            title = "Blog"
+            url = "https://example.org"
+            [search]
+            enabled = true
+            [extra]
+            color = "red"
+            logo = "logo.svg"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(theme, expected);
+    }
 }
@@ -0,0 +1,256 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use minijinja::{Error, ErrorKind, Value, value::Kwargs};
+use roxmltree::{Document, Node, NodeType, ParsingOptions};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    paths::Paths,
+    utils::{find_files, is_file},
+};
+
+pub const SPRITE: &str = "icons.svg";
+
+const SVG_NS: &str = "http://www.w3.org/2000/svg";
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Builds every SVG in `icons/` into an `icons.svg` sprite sheet with a
+/// `<symbol>` per icon, named after the file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct IconsConfig {
+    pub sprite: bool,
+}
+
+/// Reads SVGs relative to the site root, or `public/` of the site or its
+/// theme where icons used to live.
+#[derive(Debug)]
+pub struct Svgs {
+    dirs: Vec<PathBuf>,
+}
+
+impl Svgs {
+    pub fn new(paths: &Paths) -> Self {
+        let public = paths.layers().map(|p| p.public.clone());
+        Svgs {
+            dirs: std::iter::once(paths.root.clone()).chain(public).collect(),
+        }
+    }
+
+    /// The `inline_svg(path, **attributes)` template function, underscores in
+    /// attribute names become dashes so `aria_hidden=true` works.
+    pub fn function(&self, path: &str, kwargs: Kwargs) -> Result<Value, Error> {
+        let attributes = kwargs
+            .args()
+            .map(|name| {
+                Ok((
+                    name.replace('_', "-"),
+                    kwargs.get::<Value>(name)?.to_string(),
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let svg = self.inline(path, &attributes).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("Could not inline {path}: {err:#}"),
+            )
+        })?;
+
+        Ok(Value::from_safe_string(svg))
+    }
+
+    pub fn inline(&self, path: &str, attributes: &[(String, String)]) -> Result<String> {
+        let file = self
+            .dirs
+            .iter()
+            .map(|dir| dir.join(path))
+            .find(|file| file.is_file())
+            .with_context(|| format!("Could not find {path} in the site or public/"))?;
+
+        optimize(&std::fs::read_to_string(file)?, attributes)
+    }
+}
+
+/// Strips comments, metadata and editor attributes from an SVG and collapses
+/// groups without attributes, with `attributes` added to the root element.
+pub fn optimize(svg: &str, attributes: &[(String, String)]) -> Result<String> {
+    let document = parse(svg)?;
+    let root = document.root_element();
+
+    let mut out = String::from("<svg");
+    write_namespaces(&mut out, root)?;
+    for attribute in root.attributes() {
+        if attributes.iter().any(|(name, _)| *name == attribute.name()) {
+            continue;
+        }
+        if let Some(name) = attribute_name(&attribute) {
+            write!(out, " {name}=\"{}\"", escape(attribute.value()))?;
+        }
+    }
+    for (name, value) in attributes {
+        write!(out, " {name}=\"{}\"", escape(value))?;
+    }
+    out.push('>');
+    write_children(&mut out, root)?;
+    out.push_str("</svg>");
+
+    Ok(out)
+}
+
+/// Builds a sprite sheet of every SVG in `dir`, the symbol ids are the paths
+/// of the files without extension and `/` replaced by `-`.
+pub fn sprite(dir: &Path) -> Result<String> {
+    let mut files = find_files(dir, is_file)
+        .filter(|f| f.extension().is_some_and(|e| e == "svg"))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let mut out = format!("<svg xmlns=\"{SVG_NS}\" xmlns:xlink=\"{XLINK_NS}\">");
+    for file in files {
+        let id = file
+            .strip_prefix(dir)?
+            .with_extension("")
+            .to_string_lossy()
+            .replace(['/', '\\'], "-");
+        let svg = std::fs::read_to_string(&file)?;
+        let document = parse(&svg).with_context(|| format!("Invalid SVG {}", file.display()))?;
+        let root = document.root_element();
+
+        write!(out, "<symbol id=\"{}\"", escape(&id))?;
+        if let Some(view_box) = root.attribute("viewBox") {
+            write!(out, " viewBox=\"{}\"", escape(view_box))?;
+        }
+        out.push('>');
+        write_children(&mut out, root)?;
+        out.push_str("</symbol>");
+    }
+    out.push_str("</svg>");
+
+    Ok(out)
+}
+
+fn parse(svg: &str) -> Result<Document<'_>> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    let document = Document::parse_with_options(svg, options)?;
+    if document.root_element().tag_name().name() != "svg" {
+        bail!("Not an SVG");
+    }
+
+    Ok(document)
+}
+
+fn write_namespaces(out: &mut String, root: Node) -> Result<()> {
+    write!(out, " xmlns=\"{SVG_NS}\"")?;
+    let uses_xlink = root
+        .descendants()
+        .flat_map(|n| n.attributes())
+        .any(|a| a.namespace() == Some(XLINK_NS));
+    if uses_xlink {
+        write!(out, " xmlns:xlink=\"{XLINK_NS}\"")?;
+    }
+
+    Ok(())
+}
+
+fn write_children(out: &mut String, node: Node) -> Result<()> {
+    for child in node.children() {
+        match child.node_type() {
+            NodeType::Element => write_element(out, child)?,
+            NodeType::Text => {
+                let text = child.text().unwrap_or_default();
+                if !text.trim().is_empty() {
+                    out.push_str(&escape(text));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn write_element(out: &mut String, node: Node) -> Result<()> {
+    let tag = node.tag_name();
+    if !matches!(tag.namespace(), None | Some(SVG_NS)) || tag.name() == "metadata" {
+        return Ok(());
+    }
+
+    let attributes = node
+        .attributes()
+        .filter_map(|a| Some((attribute_name(&a)?, a.value())))
+        .collect::<Vec<_>>();
+
+    // A group without attributes does nothing
+    if tag.name() == "g" && attributes.is_empty() {
+        return write_children(out, node);
+    }
+
+    write!(out, "<{}", tag.name())?;
+    for (name, value) in attributes {
+        write!(out, " {name}=\"{}\"", escape(value))?;
+    }
+
+    if node.has_children() {
+        out.push('>');
+        write_children(out, node)?;
+        write!(out, "</{}>", tag.name())?;
+    } else {
+        out.push_str("/>");
+    }
+
+    Ok(())
+}
+
+/// The name to write an attribute with, or nothing for attributes in editor
+/// namespaces like `inkscape:` and `sodipodi:`.
+fn attribute_name(attribute: &roxmltree::Attribute) -> Option<String> {
+    match attribute.namespace() {
+        None => Some(attribute.name().to_string()),
+        Some(XLINK_NS) => Some(format!("xlink:{}", attribute.name())),
+        Some(XML_NS) => Some(format!("xml:{}", attribute.name())),
+        Some(_) => None,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::optimize;
+
+    #[test]
+    fn test_optimize() {
+        let svg = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- Generator: Editor -->
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" viewBox="0 0 24 24" class="old" inkscape:version="1.0">
+  <metadata><rdf>Author</rdf></metadata>
+  <g>
+    <g fill="currentColor">
+      <path d="M0 0h24v24H0z" inkscape:label="bg"/>
+    </g>
+  </g>
+</svg>"#;
+
+        let attributes = [
+            ("class".to_string(), "icon".to_string()),
+            ("aria-hidden".to_string(), "true".to_string()),
+        ];
+        assert_eq!(
+            optimize(svg, &attributes).unwrap(),
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" class="icon" aria-hidden="true"><g fill="currentColor"><path d="M0 0h24v24H0z"/></g></svg>"#
+        );
+    }
+}
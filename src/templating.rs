@@ -1,17 +1,40 @@
-use std::{fs::read_to_string, path::Path};
+use std::{
+    io::ErrorKind as IoErrorKind,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
 
 use ahash::AHashMap;
-use anyhow::{Context, Result, anyhow};
-use minijinja::{Environment, State, Value, context, path_loader};
+use anyhow::{Context, Result, anyhow, bail};
+use minijinja::{
+    AutoEscape, Environment, Error, ErrorKind, State, Value, context, default_auto_escape_callback,
+    value::Kwargs,
+};
 use minijinja_autoreload::AutoReloader;
 use minijinja_contrib::add_to_environment;
+use rhai::serde::to_dynamic;
 
 use crate::{
     build_mode::BuildMode,
     context::Context as SContext,
-    utils::{filename, find_files, is_file, unprefixed_parent},
+    data,
+    diagnostics::Diagnostic,
+    filters,
+    images::Images,
+    links::Links,
+    paths::Paths,
+    plugins::Plugins,
+    scripts::Scripts,
+    statisk_config::StatiskConfig,
+    svg::Svgs,
+    utils::{extension, filename, find_files, is_file, unprefixed_parent},
 };
 
+const DEFAULT_LAYOUT: &str = "_default";
+/// Templates in the theme are named by their path in it with this in front,
+/// as the theme does not have to be inside the site.
+const THEME_PREFIX: &str = "theme:";
+
 pub fn is_page(path: &Path) -> bool {
     !is_template(path) && !is_partial(path)
 }
@@ -40,66 +63,168 @@ fn get_asset(state: &State, name: &str) -> Option<Value> {
     Some(filename.into())
 }
 
+/// A template loaded by its path relative to the site root, so errors point
+/// at the real file.
 #[derive(Debug)]
 pub struct Template {
-    pub content: String,
+    pub name: String,
 }
 
+/// The directory, name and file extension of a template.
 #[derive(Debug, Eq, PartialEq, Hash)]
-pub struct TemplatePath(pub Option<String>, pub String);
+pub struct TemplatePath(pub Option<String>, pub String, pub String);
 
 pub struct Templates {
+    pub root: PathBuf,
+    pub theme: Option<PathBuf>,
+    /// The templates of the site and then its theme.
+    pub dirs: Vec<PathBuf>,
     pub environment: AutoReloader,
+    pub links: Links,
+    pub plugins: Arc<Plugins>,
     pub templates: AHashMap<TemplatePath, Template>,
 }
 
-pub fn create_base_context(mode: BuildMode, context: &SContext) -> Value {
-    let pages = context
-        .pages
-        .iter()
-        .filter(|c| c.value().is_public_page())
-        .map(|c| c.context(context).unwrap())
-        .collect::<Vec<_>>();
+/// Scripts and data are loaded with the environment, so errors in them are
+/// reported when rendering like template errors.
+fn load_error(err: anyhow::Error) -> Error {
+    Error::new(ErrorKind::InvalidOperation, format!("{err:#}"))
+}
 
+pub fn create_base_context(
+    env: &Environment,
+    mode: BuildMode,
+    context: &SContext,
+) -> Result<Value> {
+    Ok(context! {
+        ..create_site_context(mode, context),
+        ..context! { pages => context.pages_context(env)? }
+    })
+}
+
+/// The base context without `pages`, for rendering content bodies where
+/// listing every page would recurse back into the page being rendered.
+pub fn create_site_context(mode: BuildMode, context: &SContext) -> Value {
     context! {
         mode => mode,
         is_dev => mode.normal(),
         assets => *context.assets,
         config => context.config,
-        pages => pages
     }
 }
 
 impl Templates {
-    pub fn new(root: &Path) -> Result<Self> {
-        let template_path = root.to_path_buf();
+    pub fn new(
+        paths: &Paths,
+        config: &StatiskConfig,
+        links: Links,
+        plugins: Arc<Plugins>,
+    ) -> Result<Self> {
+        let template_dirs = paths
+            .layers()
+            .map(|p| p.templates.clone())
+            .collect::<Vec<_>>();
+        let content_path = paths.content.clone();
+        let site_root = paths.root.clone();
+        let theme_root = paths.theme.as_ref().map(|theme| theme.root.clone());
+        let page_dirs = template_dirs
+            .iter()
+            .chain([&paths.content])
+            .map(|dir| format!("{}/", name_in(&paths.root, theme_root.as_deref(), dir)))
+            .collect::<Vec<_>>();
+        let images = Arc::new(Images::new(paths));
+        let svgs = Arc::new(Svgs::new(paths));
+        let dirs = template_dirs.clone();
+        let theme = theme_root.clone();
+        let env_links = links.clone();
+        let env_plugins = plugins.clone();
+        let (env_paths, env_config) = (paths.clone(), to_dynamic(config)?);
         let env = AutoReloader::new(move |notifier| {
             let mut env = Environment::new();
-            env.set_loader(path_loader(&template_path));
+            let (templates, site) = (template_dirs.clone(), site_root.clone());
+            let theme = theme_root.clone();
+            env.set_loader(move |name| load_template(&templates, &site, theme.as_deref(), name));
             add_to_environment(&mut env);
             env.add_function("get_asset", get_asset);
+            filters::register(&mut env);
+            let images = images.clone();
+            env.add_function("image", move |path: String, kwargs: Kwargs| {
+                images.function(&path, kwargs)
+            });
+            let svgs = svgs.clone();
+            env.add_function("inline_svg", move |path: String, kwargs: Kwargs| {
+                svgs.function(&path, kwargs)
+            });
+            let links = env_links.clone();
+            env.add_function("url_for", move |path: String, kwargs: Kwargs| {
+                links.url_for_function(&path, kwargs)
+            });
+            let links = env_links.clone();
+            env.add_function("get_page", move |path: String| {
+                links.get_page_function(&path)
+            });
+            env_plugins.register(&mut env);
+            let data = data::load(&env_paths).map_err(load_error)?;
+            let scripts = Scripts::load(&env_paths, env_config.clone(), &data, env_links.clone())
+                .map_err(load_error)?;
+            Arc::new(scripts).register(&mut env);
+            env.add_global("data", data);
 
+            env.set_debug(true);
             env.set_trim_blocks(true);
             env.set_lstrip_blocks(true);
+            // Pages and layouts are loaded by their path from the site root,
+            // and like before they are rendered without escaping
+            let page_dirs = page_dirs.clone();
+            env.set_auto_escape_callback(move |name| {
+                if page_dirs.iter().any(|dir| name.starts_with(dir.as_str())) {
+                    AutoEscape::None
+                } else if name.ends_with(".svg") {
+                    AutoEscape::Html
+                } else {
+                    default_auto_escape_callback(name)
+                }
+            });
 
+            // Template changes only clear templates, see `reload` for scripts
+            // and data
             notifier.set_fast_reload(true);
 
-            notifier.watch_path(&template_path, true);
+            for dir in &template_dirs {
+                notifier.watch_path(dir, true);
+            }
+            notifier.watch_path(&content_path, true);
             Ok(env)
         });
 
         let mut templates = Templates {
+            root: paths.root.clone(),
+            theme,
+            dirs,
             environment: env,
+            links,
+            plugins,
             templates: AHashMap::new(),
         };
 
-        for file in find_files(root, is_file) {
-            templates.add_template(&file, root)?;
+        // Layouts in the site replace those in the theme
+        for layer in paths.layers().collect::<Vec<_>>().into_iter().rev() {
+            for file in find_files(&layer.templates, is_file) {
+                templates.add_template(&file, &layer.templates)?;
+            }
         }
 
         Ok(templates)
     }
 
+    /// Creates the environment again when it is next acquired, which loads
+    /// scripts and data again as they are only loaded with it.
+    pub fn reload(&self) {
+        let notifier = self.environment.notifier();
+        notifier.set_fast_reload(false);
+        notifier.request_reload();
+    }
+
     pub fn add_template(&mut self, path: &Path, root: &Path) -> Result<()> {
         let name = path
             .file_stem()
@@ -108,35 +233,248 @@ impl Templates {
             .replace(['[', ']'], "");
 
         let dir = unprefixed_parent(path, root);
+        let is_default = dir.is_none() && name == DEFAULT_LAYOUT;
 
-        let content = read_to_string(path).context("could not read file")?;
-        let tmpl_path = TemplatePath(dir, name);
+        let tmpl_path = TemplatePath(dir, name, extension(path));
 
-        if is_template(path) {
-            let template = Template { content };
+        if is_template(path) || is_default {
+            let template = Template {
+                name: self.template_name(path),
+            };
             self.templates.insert(tmpl_path, template);
         }
 
         Ok(())
     }
 
-    pub fn render_template(&self, path: &TemplatePath, context: Value) -> Result<String> {
-        let template = self
-            .templates
-            .get(path)
-            .ok_or_else(|| anyhow!("Could not find template"))?;
+    /// Finds the layout for a piece of content in the given `format`, either
+    /// the explicitly named `[layout].html` or the first dynamic template
+    /// found walking from `dir` up to the root, falling back to
+    /// `_default.html`.
+    pub fn find_layout(
+        &self,
+        layout: Option<&str>,
+        dir: Option<&str>,
+        format: &str,
+    ) -> Result<&Template> {
+        if let Some(layout) = layout {
+            return self
+                .templates
+                .get(&TemplatePath(None, layout.to_string(), format.to_string()))
+                .ok_or_else(|| anyhow!("Could not find layout, searched: [{layout}].{format}"));
+        }
+
+        let mut searched = Vec::new();
+        let mut dir = dir.map(Path::new);
+        loop {
+            let key = dir.map(|d| d.to_string_lossy().to_string());
+            if let Some(template) = self.dynamic_template(key.as_deref(), format)? {
+                return Ok(template);
+            }
 
-        let env = self.environment.acquire_env()?;
-        let template = env.template_from_str(&template.content)?;
+            searched.push(match &key {
+                Some(dir) => format!("{dir}/[*].{format}"),
+                None => format!("[*].{format}"),
+            });
+
+            match dir {
+                Some(d) => dir = d.parent().filter(|p| p.components().count() > 0),
+                None => break,
+            }
+        }
+
+        searched.push(format!("{DEFAULT_LAYOUT}.{format}"));
+        self.templates
+            .get(&TemplatePath(
+                None,
+                DEFAULT_LAYOUT.to_string(),
+                format.to_string(),
+            ))
+            .ok_or_else(|| anyhow!("Could not find layout, searched: {}", searched.join(", ")))
+    }
+
+    pub fn render_template(
+        &self,
+        env: &Environment,
+        template: &Template,
+        context: Value,
+    ) -> Result<String> {
+        let template = env.get_template(&template.name)?;
         template.render(context).context("Could not render")
     }
+
+    /// Turns template errors into a [`Diagnostic`] with the page at `path`,
+    /// other errors are returned as is.
+    pub fn diagnose(&self, err: anyhow::Error, path: &Path) -> anyhow::Error {
+        let page = self.template_name(path);
+        let source = |name: &str| {
+            load_template(&self.dirs, &self.root, self.theme.as_deref(), name)
+                .ok()
+                .flatten()
+        };
+        match Diagnostic::new(&err, &page, source) {
+            Some(diagnostic) => diagnostic
+                .rename(|name| {
+                    self.dirs
+                        .iter()
+                        .map(|dir| dir.join(name))
+                        .find(|path| path.is_file())
+                        .map_or_else(|| name.to_string(), |path| self.template_name(&path))
+                })
+                .into(),
+            None => err,
+        }
+    }
+
+    /// The name a file is loaded by, see [`name_in`].
+    pub fn template_name(&self, path: &Path) -> String {
+        name_in(&self.root, self.theme.as_deref(), path)
+    }
+
+    fn dynamic_template(&self, dir: Option<&str>, format: &str) -> Result<Option<&Template>> {
+        let mut candidates = self
+            .templates
+            .iter()
+            .filter(|(path, _)| {
+                path.0.as_deref() == dir && path.1 != DEFAULT_LAYOUT && path.2 == format
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|(a, _), (b, _)| a.1.cmp(&b.1));
+
+        match candidates[..] {
+            [] => Ok(None),
+            [(_, template)] => Ok(Some(template)),
+            _ => match candidates.iter().find(|(path, _)| path.1 == "page") {
+                Some((_, template)) => Ok(Some(template)),
+                None => bail!(
+                    "Found multiple layouts in {}: {}",
+                    dir.unwrap_or("templates"),
+                    candidates
+                        .iter()
+                        .map(|(path, _)| format!("[{}].{format}", path.1))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            },
+        }
+    }
+}
+
+/// The name a file is loaded by, its path relative to the theme with `theme:`
+/// in front, or else relative to the site root.
+fn name_in(root: &Path, theme: Option<&Path>, path: &Path) -> String {
+    let name = match theme.and_then(|theme| path.strip_prefix(theme).ok()) {
+        Some(path) => format!("{THEME_PREFIX}{}", path.to_string_lossy()),
+        None => path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string(),
+    };
+    name.replace('\\', "/")
+}
+
+/// Loads templates relative to `templates/` of the site or its theme, or to
+/// the site root for pages elsewhere and to the theme for names starting with
+/// `theme:`, with frontmatter blanked out so line numbers in errors still match
+/// the file.
+fn load_template(
+    templates: &[PathBuf],
+    root: &Path,
+    theme: Option<&Path>,
+    name: &str,
+) -> Result<Option<String>, Error> {
+    let (dirs, name) = match (name.strip_prefix(THEME_PREFIX), theme) {
+        (Some(name), Some(theme)) => (vec![theme], name),
+        (Some(_), None) => return Ok(None),
+        (None, _) => (
+            templates
+                .iter()
+                .map(PathBuf::as_path)
+                .chain([root])
+                .collect(),
+            name,
+        ),
+    };
+    let name = Path::new(name);
+    if !name.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Ok(None);
+    }
+
+    for dir in dirs {
+        match std::fs::read_to_string(dir.join(name)) {
+            Ok(source) => return Ok(Some(blank_frontmatter(&source))),
+            Err(err) if err.kind() == IoErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("Could not read template {}", name.display()),
+                )
+                .with_source(err));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Replaces the frontmatter of `source` with as many empty lines, so line
+/// numbers in errors still match the file.
+pub fn blank_frontmatter(source: &str) -> String {
+    let Some(rest) = source.trim_start().strip_prefix("+++") else {
+        return source.to_string();
+    };
+    let Some(end) = rest.find("+++") else {
+        return source.to_string();
+    };
+
+    let frontmatter = source.len() - rest.len() + end + 3;
+    let lines = source[..frontmatter].matches('\n').count();
+    format!("{}{}", "\n".repeat(lines), &source[frontmatter..])
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
+    use std::{path::PathBuf, sync::Arc};
+
+    use ahash::AHashMap;
+    use minijinja::Environment;
+    use minijinja_autoreload::AutoReloader;
+    use url::Url;
 
-    use crate::templating::{is_page, is_template};
+    use crate::{
+        links::Links,
+        templating::{
+            Template, TemplatePath, Templates, blank_frontmatter, is_page, is_template,
+            load_template, name_in,
+        },
+        utils::TestDir,
+    };
+
+    fn templates(paths: &[(Option<&str>, &str, &str)]) -> Templates {
+        let templates = paths
+            .iter()
+            .map(|(dir, name, ext)| {
+                let path = TemplatePath(
+                    dir.map(ToString::to_string),
+                    (*name).to_string(),
+                    (*ext).to_string(),
+                );
+                let name = format!("{}/{name}.{ext}", dir.unwrap_or_default());
+                (path, Template { name })
+            })
+            .collect::<AHashMap<_, _>>();
+
+        Templates {
+            root: PathBuf::new(),
+            theme: None,
+            dirs: Vec::new(),
+            environment: AutoReloader::new(|_| Ok(Environment::new())),
+            links: Links::new(&Url::parse("http://localhost:3000").unwrap()),
+            plugins: Arc::default(),
+            templates,
+        }
+    }
 
     #[test]
     fn test_is_page() {
@@ -153,4 +491,82 @@ mod tests {
             assert!(is_template(&PathBuf::from(page)));
         }
     }
+
+    #[test]
+    fn test_find_layout_fallback() {
+        let templates = templates(&[
+            (Some("posts"), "post", "html"),
+            (None, "page", "html"),
+            (None, "_default", "html"),
+        ]);
+
+        let layout = |dir| {
+            let layout = templates.find_layout(None, dir, "html").unwrap();
+            layout.name.clone()
+        };
+        assert_eq!(layout(Some("posts")), "posts/post.html");
+        assert_eq!(layout(Some("posts/2024")), "posts/post.html");
+        assert_eq!(layout(Some("pages")), "/page.html");
+        assert_eq!(layout(None), "/page.html");
+    }
+
+    #[test]
+    fn test_find_layout_format() {
+        let templates = templates(&[(Some("posts"), "post", "html"), (None, "page", "json")]);
+
+        let layout = templates.find_layout(None, Some("posts"), "json").unwrap();
+        assert_eq!(layout.name, "/page.json");
+        assert!(templates.find_layout(Some("post"), None, "json").is_err());
+    }
+
+    #[test]
+    fn test_find_layout_default() {
+        let templates = templates(&[(None, "_default", "html")]);
+        let layout = templates.find_layout(None, Some("posts"), "html").unwrap();
+        assert_eq!(layout.name, "/_default.html");
+    }
+
+    #[test]
+    fn test_find_layout_reports_candidates() {
+        let templates = templates(&[(Some("pages"), "page", "html")]);
+        let err = templates
+            .find_layout(None, Some("posts/2024"), "html")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not find layout, searched: posts/2024/[*].html, posts/[*].html, [*].html, _default.html"
+        );
+    }
+
+    #[test]
+    fn test_blank_frontmatter() {
+        let source = "+++\ntitle = \"Home\"\n+++\n<h1>{{ title }}</h1>";
+        assert_eq!(blank_frontmatter(source), "\n\n\n<h1>{{ title }}</h1>");
+        assert_eq!(blank_frontmatter("<p>a+++b</p>"), "<p>a+++b</p>");
+    }
+
+    #[test]
+    fn test_theme_outside_site() {
+        let dir = TestDir::new("theme");
+        let (site, theme) = (dir.path().join("site"), dir.path().join("shared"));
+        std::fs::create_dir_all(theme.join("templates/pages")).unwrap();
+        std::fs::write(theme.join("templates/pages/[page].html"), "{{ content }}").unwrap();
+
+        let layout = theme.join("templates/pages/[page].html");
+        let name = name_in(&site, Some(&theme), &layout);
+        assert_eq!(name, "theme:templates/pages/[page].html");
+        assert_eq!(
+            name_in(&site, Some(&theme), &site.join("index.html")),
+            "index.html"
+        );
+
+        let templates = [site.join("templates"), theme.join("templates")];
+        let source = load_template(&templates, &site, Some(&theme), &name).unwrap();
+        assert_eq!(source.as_deref(), Some("{{ content }}"));
+        assert!(
+            load_template(&templates, &site, Some(&theme), "theme:../site/x.html")
+                .unwrap()
+                .is_none()
+        );
+    }
 }
@@ -1,140 +1,185 @@
-use std::{path::Path, thread};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 
-use ahash::HashSet;
-use anyhow::{Context, Result};
+use anyhow::Result;
 use notify::{
     Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher, event::ModifyKind,
 };
 
 use crate::{
     BuildMode,
-    asset::{Asset, is_buildable_css_file, is_js},
-    context::{Context as AppContext, collect_content, collect_pages},
+    context::{Context as AppContext, collect_content, collect_css, collect_js, collect_pages},
     paths::Paths,
     templating::is_partial,
-    utils::find_files,
 };
 
+/// How long to wait for more changes, so saving or generating several files
+/// at once only rebuilds once.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
 pub fn start_live_reload(paths: &Paths, context: &AppContext) {
     thread::scope(|scope| {
-        let templates = scope.spawn(|| {
-            file_watcher(
-                &paths.templates.canonicalize()?,
-                &["html", "xml", "xsl"],
-                |event| {
-                    for path in event.paths.iter().collect::<HashSet<_>>() {
-                        templates_watch_handler(paths, path, context)?;
-                    }
-                    Ok(())
-                },
-            )
-        });
-
-        let css = scope.spawn(|| {
-            file_watcher(&paths.css.canonicalize()?, &["css"], |event| {
-                for path in event.paths.iter().collect::<HashSet<_>>() {
-                    css_watch_handler(paths, path, context)?;
-                }
-                Ok(())
-            })
-        });
-
-        let js = scope.spawn(|| {
-            file_watcher(&paths.js.canonicalize()?, &["js", "cjs"], |event| {
-                for path in event.paths.iter().collect::<HashSet<_>>() {
-                    js_watch_handler(paths, path, context)?;
-                }
-                Ok(())
-            })
-        });
-
-        let content = scope.spawn(|| {
-            file_watcher(&paths.content.canonicalize()?, &["kladd"], |event| {
-                for path in event
-                    .paths
-                    .iter()
-                    .filter(|p| !is_partial(p))
-                    .collect::<HashSet<_>>()
-                {
-                    content_watch_handler(paths, path, context)?;
-                }
-                Ok(())
+        let mut watchers = Vec::new();
+
+        // Watch the site and its theme, a theme only has some of the directories
+        for layer in paths.layers() {
+            if layer.templates.is_dir() {
+                watchers.push(scope.spawn(move || {
+                    file_watcher(
+                        &layer.templates.canonicalize()?,
+                        &["html", "xml", "xsl", "json"],
+                        |changed| {
+                            context.rebuild_pages(paths, &changed, || {
+                                pages_watch_handler(paths, &changed, context, true)
+                            });
+                        },
+                    )
+                }));
+            }
+
+            if layer.scripts.is_dir() {
+                watchers.push(scope.spawn(move || {
+                    file_watcher(&layer.scripts.canonicalize()?, &["rhai"], |changed| {
+                        context.rebuild_pages(paths, &changed, || {
+                            context.templates.reload();
+                            pages_watch_handler(paths, &changed, context, true)
+                        });
+                    })
+                }));
+            }
+
+            if layer.data.is_dir() {
+                watchers.push(scope.spawn(move || {
+                    file_watcher(&layer.data.canonicalize()?, &["toml", "json"], |changed| {
+                        context.rebuild_pages(paths, &changed, || {
+                            context.templates.reload();
+                            pages_watch_handler(paths, &changed, context, true)
+                        });
+                    })
+                }));
+            }
+
+            if layer.css.is_dir() {
+                watchers.push(scope.spawn(move || {
+                    file_watcher(&layer.css.canonicalize()?, &["css"], |changed| {
+                        context.rebuild(|| css_watch_handler(paths, &changed, context));
+                    })
+                }));
+            }
+
+            if layer.js.is_dir() {
+                watchers.push(scope.spawn(move || {
+                    file_watcher(&layer.js.canonicalize()?, &["js", "cjs"], |changed| {
+                        context.rebuild(|| js_watch_handler(paths, &changed, context));
+                    })
+                }));
+            }
+        }
+
+        watchers.push(scope.spawn(|| {
+            file_watcher(&paths.content.canonicalize()?, &["kladd"], |changed| {
+                // Partials can be included by any page
+                let rerender = changed.iter().any(|p| is_partial(p));
+                context.rebuild_pages(paths, &changed, || {
+                    pages_watch_handler(paths, &changed, context, rerender)
+                });
             })
-        });
+        }));
 
-        css.join().unwrap().unwrap();
-        js.join().unwrap().unwrap();
-        content.join().unwrap().unwrap();
-        templates.join().unwrap().unwrap();
+        for watcher in watchers {
+            if let Err(err) = watcher.join().unwrap() {
+                tracing::error!("Could not watch for changes: {err:#}");
+            }
+        }
     });
 }
 
-fn css_watch_handler(paths: &Paths, path: &Path, context: &AppContext) -> Result<()> {
+fn css_watch_handler(paths: &Paths, changed: &[PathBuf], context: &AppContext) -> Result<()> {
     tracing::info!(
         "File(s) {:?} changed, rebuilding CSS",
-        strip_prefix_paths(&paths.root, path)?
+        strip_prefix_paths(&paths.root, changed)?
     );
-    for file in find_files(&paths.css, is_buildable_css_file) {
-        let css = Asset::build_css(&file, BuildMode::Normal)?;
+    for css in collect_css(paths, BuildMode::Normal)? {
         context.update_asset(css.source_name.clone(), css)?;
     }
 
     Ok(())
 }
 
-fn js_watch_handler(paths: &Paths, path: &Path, context: &AppContext) -> Result<()> {
+fn js_watch_handler(paths: &Paths, changed: &[PathBuf], context: &AppContext) -> Result<()> {
     tracing::info!(
         "File(s) {:?} changed, rebuilding JS",
-        strip_prefix_paths(&paths.root, path)?
+        strip_prefix_paths(&paths.root, changed)?
     );
-    for file in find_files(&paths.js, is_js) {
-        let js = Asset::build_js(&file, BuildMode::Normal)?;
+    for js in collect_js(paths, BuildMode::Normal)? {
         context.update_asset(js.source_name.clone(), js)?;
     }
 
     Ok(())
 }
 
-fn content_watch_handler(paths: &Paths, path: &Path, context: &AppContext) -> Result<()> {
+/// Reads all content and template pages again, which also picks up the files
+/// the `pre_build` hooks wrote. Templates, scripts and data can be used by any
+/// page, so changes to them `rerender` every page.
+fn pages_watch_handler(
+    paths: &Paths,
+    changed: &[PathBuf],
+    context: &AppContext,
+    rerender: bool,
+) -> Result<()> {
     tracing::info!(
-        "Content {:?} changed, rebuilding...",
-        strip_prefix_paths(&paths.root, path)?
+        "File(s) {:?} changed, rebuilding...",
+        strip_prefix_paths(&paths.root, changed)?
     );
-    for page in collect_content(paths)? {
-        context.update_page(page.filename(), page)?;
-    }
+    let pages = collect_content(paths)?
+        .into_iter()
+        .chain(collect_pages(paths)?)
+        .collect();
 
-    Ok(())
+    context.update_pages(pages, rerender)
 }
 
-fn templates_watch_handler(paths: &Paths, path: &Path, context: &AppContext) -> Result<()> {
-    tracing::info!(
-        "Template {:?} changed, rebuilding...",
-        strip_prefix_paths(&paths.root, path)?
-    );
-    for page in collect_pages(paths)? {
-        context.update_page(page.filename(), page)?;
-    }
-
-    Ok(())
-}
-
-fn strip_prefix_paths(prefix: impl AsRef<Path>, path: &Path) -> Result<&Path> {
-    path.strip_prefix(prefix.as_ref().canonicalize()?)
-        .context("could not strip prefix")
+/// The `changed` paths relative to `prefix`, files outside of it like those
+/// in a shared theme keep their full path.
+fn strip_prefix_paths(prefix: impl AsRef<Path>, changed: &[PathBuf]) -> Result<Vec<&Path>> {
+    let prefix = prefix.as_ref().canonicalize()?;
+    Ok(changed
+        .iter()
+        .map(|path| path.strip_prefix(&prefix).unwrap_or(path))
+        .collect())
 }
 
+/// Waits for changes to files with one of `extensions` in `path`, calling
+/// `handler` with every file changed until the changes pause for `DEBOUNCE`.
 fn file_watcher<F, const N: usize>(path: &Path, extensions: &[&str; N], handler: F) -> Result<()>
 where
-    F: Fn(Event) -> Result<()>,
+    F: Fn(Vec<PathBuf>),
 {
     let (tx, rx) = std::sync::mpsc::channel();
     let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
     watcher.watch(path, RecursiveMode::Recursive)?;
 
-    for res in rx {
-        if let Some(res) = filter_event(res, extensions) {
-            handler(res)?;
+    while let Ok(res) = rx.recv() {
+        let mut changed = BTreeSet::new();
+        changed.extend(
+            filter_event(res, extensions)
+                .into_iter()
+                .flat_map(|e| e.paths),
+        );
+        while let Ok(res) = rx.recv_timeout(DEBOUNCE) {
+            changed.extend(
+                filter_event(res, extensions)
+                    .into_iter()
+                    .flat_map(|e| e.paths),
+            );
+        }
+
+        if !changed.is_empty() {
+            handler(changed.into_iter().collect());
         }
     }
 
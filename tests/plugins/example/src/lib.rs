@@ -0,0 +1,30 @@
+use statisk_plugin::{Page, Plugin, Value, export_plugin};
+
+fn shout(args: &[Value]) -> Result<Value, String> {
+    match args.first() {
+        Some(Value::String(s)) => Ok(Value::from(s.to_uppercase())),
+        _ => Err("shout expects a string".to_string()),
+    }
+}
+
+fn answer(_args: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(42))
+}
+
+/// Replaces the `:wave:` shortcode with an emoji.
+fn wave(_page: &Page, content: &str) -> Result<String, String> {
+    Ok(content.replace(":wave:", "👋"))
+}
+
+/// Marks links to other sites as external.
+fn external_links(_page: &Page, content: &str) -> Result<String, String> {
+    Ok(content.replace(r#"<a href="http"#, r#"<a rel="external" href="http"#))
+}
+
+export_plugin!(
+    Plugin::new()
+        .filter("shout", shout)
+        .function("answer", answer)
+        .before_html(wave)
+        .after_html(external_links)
+);